@@ -0,0 +1,219 @@
+//! Benchmarks of the storage layer.
+//!
+//! Run with `cargo bench`. There is no benchmarking framework available, so each scenario is simply
+//! timed a few times with [`Instant`], and the best run is reported.
+
+use parity_scale_codec::{Decode, Encode};
+use pba_pre_course_assignment::l_mini_substrate::{
+	currency_module::{self, AccountBalance, BalancesMap},
+	io_storage::{self, InMemoryBackend, Key, StorageBackend, TestExternalities, Value},
+	runtime::{MyRuntime, RuntimeCall},
+	shared::{AccountId, Dispatchable, StorageMap, StorageValue, ValueQuery},
+};
+use std::{
+	ops::Bound,
+	time::{Duration, Instant},
+};
+
+/// Creates the storage a scenario runs against.
+type Setup = fn() -> TestExternalities;
+
+/// The number of times each scenario is run.
+const RUNS: usize = 5;
+
+/// Run `f` in a fresh storage [`RUNS`] times, and return the fastest run.
+fn bench(name: &str, setup: impl Fn() -> TestExternalities, f: impl Fn()) -> Duration {
+	let best = (0..RUNS)
+		.map(|_| {
+			let mut ext = setup();
+			ext.execute_with(|| {
+				let start = Instant::now();
+				f();
+				start.elapsed()
+			})
+		})
+		.min()
+		.expect("`RUNS` is not zero; qed");
+	println!("{:<40} {:>10.2?}", name, best);
+	best
+}
+
+/// Print how much faster `new` is than `old`.
+fn speedup(old: Duration, new: Duration) {
+	println!(
+		"{:<40} {:>9.2}x",
+		"speedup",
+		old.as_secs_f64() / new.as_secs_f64()
+	);
+}
+
+/// A backend that simulates a database, where every read has a fixed cost on top of the lookup.
+#[derive(Default)]
+struct SlowBackend(InMemoryBackend);
+
+impl SlowBackend {
+	const READ_COST: Duration = Duration::from_micros(1);
+
+	fn pay(&self) {
+		let start = Instant::now();
+		while start.elapsed() < Self::READ_COST {}
+	}
+}
+
+impl StorageBackend for SlowBackend {
+	fn get(&self, key: &[u8]) -> Option<Value> {
+		self.pay();
+		self.0.get(key)
+	}
+
+	fn set(&mut self, key: Key, value: Value) {
+		self.0.set(key, value)
+	}
+
+	fn clear(&mut self, key: &[u8]) {
+		self.0.clear(key)
+	}
+
+	fn next_key(&self, from: Bound<&[u8]>) -> Option<Key> {
+		self.pay();
+		self.0.next_key(from)
+	}
+}
+
+/// Dispatch `count` transfers between a handful of accounts.
+fn transfers(count: u32) {
+	let minter = AccountId(42);
+	let accounts = (0..8).map(AccountId).collect::<Vec<_>>();
+	for who in accounts.iter() {
+		RuntimeCall::Currency(currency_module::Call::Mint {
+			dest: *who,
+			amount: 1_000_000,
+		})
+		.dispatch(minter)
+		.unwrap();
+	}
+	for i in 0..count as usize {
+		let from = accounts[i % accounts.len()];
+		let dest = accounts[(i + 1) % accounts.len()];
+		RuntimeCall::Currency(currency_module::Call::Transfer { dest, amount: 10 })
+			.dispatch(from)
+			.unwrap();
+	}
+}
+
+/// Repeated transfers, with and without the read cache.
+///
+/// Against a plain in-memory backend, the cache is expected to make little difference, as a cache
+/// hit costs about as much as the backend lookup it saves. It pays off once reads are costly.
+fn cache() {
+	println!("\n# repeated transfers, with and without the read cache\n");
+	let slow = || TestExternalities::from_backend(Box::<SlowBackend>::default());
+	for (backend, setup) in [
+		("in memory", TestExternalities::default as Setup),
+		("slow", slow),
+	] {
+		let uncached = bench(&format!("{}, uncached", backend), setup, || {
+			io_storage::set_cache_capacity(0);
+			transfers(20_000);
+		});
+		let cached = bench(&format!("{}, cached", backend), setup, || transfers(20_000));
+		speedup(uncached, cached);
+	}
+}
+
+type Balances = BalancesMap<MyRuntime>;
+
+/// `StorageMap::get`, as it used to be: the raw value is copied out of the storage, then decoded.
+fn copying_get(who: AccountId) -> Option<AccountBalance<MyRuntime>> {
+	io_storage::get(Balances::raw_storage_key(who))
+		.and_then(|raw_value| Decode::decode(&mut &*raw_value).ok())
+}
+
+/// `StorageMap::mutate`, as it used to be: the key is built twice, and the value copied.
+fn copying_mutate(who: AccountId, f: impl FnOnce(&mut Option<AccountBalance<MyRuntime>>)) {
+	let mut value = copying_get(who);
+	f(&mut value);
+	match value {
+		Some(value) => io_storage::set(Balances::raw_storage_key(who), value.encode()),
+		None => io_storage::clear(Balances::raw_storage_key(who)),
+	}
+}
+
+/// The storage accesses of a transfer, `exists`, `get` and `mutate`, through copying and borrowing
+/// reads.
+fn borrowed_reads() {
+	println!("\n# balance accesses, copying the value vs. reading it in place\n");
+	let accounts = (0..8).map(AccountId).collect::<Vec<_>>();
+	let setup = || {
+		TestExternalities::new(accounts.iter().map(|who| {
+			let balance = AccountBalance::<MyRuntime> {
+				free: 1_000_000,
+				reserved: 0,
+			};
+			(Balances::raw_storage_key(*who), balance.encode())
+		}))
+	};
+	let bump = |value: &mut Option<AccountBalance<MyRuntime>>| {
+		value.as_mut().expect("all accounts exist; qed").free += 1
+	};
+
+	for cache in ["cached", "uncached"] {
+		let capacity = if cache == "cached" {
+			io_storage::DEFAULT_CACHE_CAPACITY
+		} else {
+			0
+		};
+		let copying = bench(&format!("copying, {}", cache), setup, || {
+			io_storage::set_cache_capacity(capacity);
+			for i in 0..200_000 {
+				let who = accounts[i % accounts.len()];
+				assert!(copying_get(who).is_some());
+				assert!(copying_get(who).unwrap().free > 0);
+				copying_mutate(who, bump);
+			}
+		});
+		let borrowing = bench(&format!("borrowing, {}", cache), setup, || {
+			io_storage::set_cache_capacity(capacity);
+			for i in 0..200_000 {
+				let who = accounts[i % accounts.len()];
+				assert!(Balances::exists(who));
+				assert!(Balances::get(who).unwrap().free > 0);
+				Balances::mutate(who, bump);
+			}
+		});
+		speedup(copying, borrowing);
+	}
+}
+
+pba_pre_course_assignment::storage! {
+	/// A long list of numbers, e.g. the events of a block.
+	Log = value("Bench", Vec<u32>, ValueQuery);
+}
+
+/// Adding items to an ever longer list, by decoding and re-encoding it vs. appending in place.
+///
+/// The cost of `mutate` grows with the length of the list, while `append` is expected to take
+/// about the same time whatever the length. The only exception is the first write of the list in
+/// a block, which keeps a copy of it in the history, once.
+fn append() {
+	println!("\n# 1000 items added to a list, decoding it vs. appending in place\n");
+	for len in [1_000, 10_000, 100_000] {
+		let log = (0..len).collect::<Vec<u32>>().encode();
+		let setup = || TestExternalities::new([(Log::raw_storage_key(), log.clone())]);
+		let mutating = bench(&format!("mutate, {} items", len), setup, || {
+			(0..1_000).for_each(|i| Log::mutate(|log| log.push(i)));
+			assert_eq!(Log::decode_len(), Some(len as usize + 1_000));
+		});
+		let appending = bench(&format!("append, {} items", len), setup, || {
+			(0..1_000).for_each(Log::append);
+			assert_eq!(Log::decode_len(), Some(len as usize + 1_000));
+		});
+		speedup(mutating, appending);
+	}
+}
+
+fn main() {
+	cache();
+	borrowed_reads();
+	append();
+}
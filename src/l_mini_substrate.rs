@@ -149,13 +149,31 @@
 //! stored. Your implementation must adhere to the following specification:
 //!
 //! 1. For each [`shared::StorageValue`], assuming that the name of type implementing `StorageValue`
-//!    is `N`, the key should (simply) be equal to `N`, represented as bytes. For example, if the
-//!    storage value struct is called `Balance`, the key should be `b"Balance"`.
+//!    is `N`, and that it belongs to a module with the name `M`, the key should be
+//!    `concat(twox_128(M), twox_128(N))`. For example, if the storage value struct is called
+//!    `Balance`, in the module `MOD_CURRENCY`, the key should be
+//!    `concat(twox_128(b"MOD_CURRENCY"), twox_128(b"Balance"))`. See [`shared::storage_prefix`].
 //!
-//! 2. For each [`shared::StorageMap`], assuming that the name of type implementing `StorageMap` is
-//!    `N`, the final key of a map key `k` should be `concat(N, '_', encode(k))`, where `encode`
-//!    comes from `parity-scale-codec`. For example, if the storage map struct is called `Balances`,
-//!    the key for the account `alice` should be `b"Balances_alice"`.
+//! 2. For each [`shared::StorageMap`], the same prefix is used, and the final key of a map key `k`
+//!    should be `concat(twox_128(M), twox_128(N), hash(encode(k)))`, where `encode` comes from
+//!    `parity-scale-codec`, and `hash` is the [`shared::StorageHasher`] chosen by the map. For
+//!    example, if the storage map struct is called `Balances` and uses the
+//!    [`shared::Twox64Concat`] hasher, the key for the account `alice` should be
+//!    `concat(twox_128(M), twox_128(b"Balances"), twox_64(encode(alice)), encode(alice))`.
+//!
+//! 3. For each [`shared::StorageDoubleMap`], the final key of `(k1, k2)` is
+//!    `concat(twox_128(M), twox_128(N), hash1(encode(k1)), hash2(encode(k2)))`, with a hasher for
+//!    each key.
+//!
+//! 4. For each [`shared::StorageNMap`], the final key is the same prefix followed by the hashed
+//!    encoding of each part of the key, in order, each with its own hasher.
+//!
+//! Storage values and maps are best declared with the [`storage!`](crate::storage) macro, which
+//! names each item after its struct, and thus always follows the above.
+//!
+//! Prefixing everything with the module name means that two modules can use the same names for
+//! their storage items without overwriting each other. [`registry::StorageRegistry`] can check that
+//! no two items of a runtime end up with overlapping keys.
 //!
 //! See `storage_encoding` tests for more examples.
 //!
@@ -243,42 +261,13 @@
 
 use parity_scale_codec::{Decode, Encode};
 
-/// Everything to do with your storage. This is the backing storage that will support all your
-/// module and eventually the runtime. It works off a key-value basis. You ask it for value stored
-/// under some key, or store a new value under some key.
-///
-/// Both keys and values are opaque bytes (`Vec<u8>`).
-///
-/// In a real blockchain, this would be a database. In this test, we use a simple thread-local map
-/// to store all the data.
-///
-/// Not a single line in this module should change! You can even skip understanding it, just use the
-/// `get`, `set` and `clear` function when needed.
-pub mod io_storage {
-	use std::{cell::RefCell, collections::BTreeMap};
-
-	pub type Key = Vec<u8>;
-	pub type Value = Vec<u8>;
-
-	thread_local! {
-		static STORAGE: RefCell<BTreeMap<Key, Value>> = RefCell::new(BTreeMap::<Key, Value>::new());
-	}
-
-	/// Get the value under `key`.
-	pub fn get(key: Vec<u8>) -> Option<Vec<u8>> {
-		STORAGE.with(|s| s.borrow().get(&key).cloned())
-	}
-
-	/// Set the value under `key` to `value`.
-	pub fn set(key: Vec<u8>, value: Vec<u8>) {
-		STORAGE.with(|s| s.borrow_mut().insert(key, value));
-	}
-
-	/// Remove the value under `key`.
-	pub fn clear(key: Vec<u8>) {
-		STORAGE.with(|s| s.borrow_mut().remove(&key));
-	}
-}
+pub mod bounded;
+pub mod hashing;
+pub mod io_storage;
+mod macros;
+pub mod query;
+pub mod registry;
+pub mod storage_key;
 
 /// The shared functionality between all modules.
 ///
@@ -288,10 +277,21 @@ pub mod shared {
 	use super::*;
 	use num::Zero;
 	use num::{CheckedAdd, CheckedSub};
-	use std::fmt::Debug;
+	use parity_scale_codec::DecodeAll;
+	use std::{convert::Infallible, fmt::Debug};
 
 	// This is called a "re-export", we import it, and export it as well, so other modules in this
 	// file can use it as `shared::Get`.
+	pub use super::bounded::{BoundedBTreeMap, BoundedVec, ConstU32, DecodeLength};
+	use super::hashing::twox_128;
+	pub use super::hashing::{
+		Blake2_128Concat, Identity, ReversibleStorageHasher, StorageHasher, Twox64Concat,
+	};
+	use super::query::normalize;
+	pub use super::query::{GetDefault, OptionQuery, QueryKindTrait, ResultQuery, ValueQuery};
+	pub use super::storage_key::{
+		HasKeyPrefix, KeyGenerator, NMapKey, ReversibleKeyGenerator, SuffixKey,
+	};
 	pub use crate::get::Get;
 
 	/// An abstraction over the account identifier.
@@ -310,7 +310,7 @@ pub mod shared {
 	///     assert_eq!(1000u32.encode(), vec![232, 3, 0, 0]);
 	/// # }
 	/// ```
-	#[derive(Encode, Decode, Clone, Copy, PartialEq, Eq)]
+	#[derive(Encode, Decode, Clone, Copy, PartialEq, Eq, Debug)]
 	pub struct AccountId(pub u32);
 
 	/// Something that can be dispatched.
@@ -336,14 +336,45 @@ pub mod shared {
 	/// Final return type of all dispatch functions.
 	pub type DispatchResult = Result<(), DispatchError>;
 
+	/// What a storage item of values `V` returns when queried with the query kind `Q`.
+	pub type Query<V, Q> = <Q as QueryKindTrait<V>>::Query;
+
+	/// The errors that can occur while reading typed items out of the storage.
+	#[derive(Debug, PartialEq, Eq, Clone)]
+	pub enum StorageError {
+		/// Something is stored under `key`, but it cannot be decoded into the types of the storage
+		/// item that the key belongs to. Either the key itself, or the value, is corrupt.
+		Corrupt {
+			/// The raw storage key of the entry.
+			key: super::io_storage::Key,
+			/// Why decoding failed.
+			error: parity_scale_codec::Error,
+		},
+	}
+
+	/// A value that is encoded as a sequence of `Item`s, which can thus be appended to without
+	/// decoding it. See [`StorageValue::append`].
+	///
+	/// Bounded collections are deliberately left out, as appending to them could exceed their
+	/// bound.
+	pub trait StorageAppend<Item: Encode>: Encode + Decode {}
+
+	impl<T: Encode + Decode> StorageAppend<T> for Vec<T> {}
+
+	/// The prefix of the storage item called `name`, within the module called `module`:
+	/// `twox_128(module) ++ twox_128(name)`.
+	pub fn storage_prefix(module: &str, name: &str) -> super::io_storage::Key {
+		[twox_128(module.as_bytes()), twox_128(name.as_bytes())].concat()
+	}
+
 	/// Abstraction around a value stored in the storage.
 	///
 	/// This trait provides all the auto-implementation for a struct to become a storage value, via
 	/// implementing it.
 	///
-	/// The only method that must be implemented is `raw_storage_key`. This method will dictate
-	/// where should the key of this storage value be stored in the underlying storage (when
-	/// communicating with `io_storage` module).
+	/// The only methods that must be implemented are `module_prefix` and `storage_prefix`. These
+	/// dictate where the value is stored in the underlying storage (when communicating with
+	/// `io_storage` module). See [`StorageValue::raw_storage_key`].
 	///
 	/// > Traits like this that give a lot of auto-implementations by adding a small number of
 	/// > custom implementations are a powerful Rust abstraction that you should be familiar with!
@@ -351,14 +382,18 @@ pub mod shared {
 	/// ## Example
 	///
 	/// ```
-	/// # use pba_pre_course_assignment::l_mini_substrate::shared::StorageValue;
+	/// # use pba_pre_course_assignment::l_mini_substrate::shared::{OptionQuery, StorageValue};
 	///
 	/// /// A single u32 stored in storage.
 	/// struct DummyStorageValue;
 	/// impl StorageValue for DummyStorageValue {
 	///     type Value = u32;
-	///     fn raw_storage_key() -> Vec<u8> {
-	///             b"dummy_storage_value".to_vec()
+	///     type QueryKind = OptionQuery;
+	///     fn module_prefix() -> &'static str {
+	///         "Dummy"
+	///     }
+	///     fn storage_prefix() -> &'static str {
+	///         "DummyStorageValue"
 	///     }
 	/// }
 	///
@@ -417,26 +452,49 @@ pub mod shared {
 		/// It can be anything that is encode- and decode-able.
 		type Value: Encode + Decode;
 
-		/// The final storage key of `Self` as a storage value.
-		fn raw_storage_key() -> super::io_storage::Key;
+		/// How a missing value is presented, e.g. [`OptionQuery`] for `None`. See
+		/// [`QueryKindTrait`].
+		type QueryKind: QueryKindTrait<Self::Value>;
 
-		/// Get the underlying value. If it doesn't exist, return `None`.
-		fn get() -> Option<Self::Value> {
-			let key = Self::raw_storage_key();
-			super::io_storage::get(key)
-				.and_then(|raw_value| <Self::Value as Decode>::decode(&mut &*raw_value).ok())
+		/// The name of the module that this value belongs to.
+		fn module_prefix() -> &'static str;
+
+		/// The name of this value, unique within its module.
+		fn storage_prefix() -> &'static str;
+
+		/// The final storage key of `Self` as a storage value:
+		/// `twox_128(module_prefix()) ++ twox_128(storage_prefix())`.
+		fn raw_storage_key() -> super::io_storage::Key {
+			storage_prefix(Self::module_prefix(), Self::storage_prefix())
 		}
 
-		/// Check if the value exists in storage.
+		/// Get the underlying value, as presented by `Self::QueryKind` if it doesn't exist, e.g.
+		/// `None` for an [`OptionQuery`].
+		///
+		/// A value that cannot be decoded is presented as if it didn't exist, see
+		/// [`StorageValue::try_get`].
+		fn get() -> Query<Self::Value, Self::QueryKind> {
+			let value = super::io_storage::read_with(Self::raw_storage_key(), decode_raw);
+			Self::QueryKind::from_optional_value(value)
+		}
+
+		/// Like [`StorageValue::get`], but a value that cannot be decoded is reported as
+		/// [`StorageError::Corrupt`].
+		fn try_get() -> Result<Query<Self::Value, Self::QueryKind>, StorageError> {
+			try_read(&Self::raw_storage_key()).map(Self::QueryKind::from_optional_value)
+		}
+
+		/// Check if the value exists in storage, without decoding it.
 		fn exists() -> bool {
-			// Could be implemented more efficiently, guess how, and why?
-			Self::get().is_some()
+			super::io_storage::exists(Self::raw_storage_key())
 		}
 
 		/// Set a new value into the storage.
+		///
+		/// The default value of a [`ValueQuery`] is not stored, but removes the value instead.
 		fn set(new_value: Self::Value) {
-			let key = Self::raw_storage_key();
-			super::io_storage::set(key, new_value.encode())
+			let value = normalize::<_, Self::QueryKind>(Some(new_value));
+			put_raw(&Self::raw_storage_key(), value.map(|value| value.encode()))
 		}
 
 		/// Remove any value stored in this storage value.
@@ -449,21 +507,75 @@ pub mod shared {
 
 		/// Mutate the value in place based on the given `f`.
 		///
-		/// `f` will see `None` if the value doesn't exist, and `Some` if it does.
+		/// `f` sees the value as `get` would return it, e.g. `None` for a value that doesn't exist
+		/// with an [`OptionQuery`], and the value is written back in the same way as `set` would.
+		/// For an [`OptionQuery`], this means that:
 		///
 		/// If the value doesn't exists, but `f` mutates the given `None` to `Some(_)`, it will be
 		/// created.
 		///
 		/// If the value exists, but it is mutated to `None`, it will be removed.
-		fn mutate(f: impl FnOnce(&mut Option<Self::Value>)) {
-			// todo!("provide an auto-implementation of this")
-			let mut storage_value = Self::get();
-			f(&mut storage_value);
-
-			match storage_value {
-				Some(new_value) => Self::set(new_value),
-				None => Self::clear(),
-			}
+		///
+		/// A value that cannot be decoded is given to `f` as missing, but is only overwritten if
+		/// `f` sets a new value, and never removed.
+		///
+		/// Returns whatever `f` returns.
+		fn mutate<R>(f: impl FnOnce(&mut Query<Self::Value, Self::QueryKind>) -> R) -> R {
+			infallible(Self::try_mutate(|value| Ok(f(value))))
+		}
+
+		/// Like [`StorageValue::mutate`], but `f` may fail, in which case nothing is written and
+		/// the error is returned.
+		fn try_mutate<R, E>(
+			f: impl FnOnce(&mut Query<Self::Value, Self::QueryKind>) -> Result<R, E>,
+		) -> Result<R, E> {
+			Self::try_mutate_exists(|value| {
+				let mut query = Self::QueryKind::from_optional_value(value.take());
+				let result = f(&mut query)?;
+				*value = Self::QueryKind::into_optional_value(query);
+				Ok(result)
+			})
+		}
+
+		/// Mutate the value, where `f` sees `None` if the value does not exist, and can remove it
+		/// by setting it to `None`, regardless of `Self::QueryKind`.
+		fn mutate_exists<R>(f: impl FnOnce(&mut Option<Self::Value>) -> R) -> R {
+			infallible(Self::try_mutate_exists(|value| Ok(f(value))))
+		}
+
+		/// Like [`StorageValue::mutate_exists`], but `f` may fail, in which case nothing is
+		/// written and the error is returned.
+		fn try_mutate_exists<R, E>(
+			f: impl FnOnce(&mut Option<Self::Value>) -> Result<R, E>,
+		) -> Result<R, E> {
+			try_mutate_raw::<_, Self::QueryKind, _, _>(&Self::raw_storage_key(), f)
+		}
+
+		/// Remove the value from the storage, returning it as `get` would.
+		fn take() -> Query<Self::Value, Self::QueryKind> {
+			Self::QueryKind::from_optional_value(Self::mutate_exists(Option::take))
+		}
+
+		/// Add `item` at the end of the stored sequence, without decoding it, see
+		/// [`super::io_storage::append`]. A missing value is created with just `item` in it.
+		///
+		/// A stored value that does not start with a valid length is left untouched, and `item` is
+		/// dropped. Use [`StorageValue::try_get`] to find out whether the value is corrupt.
+		fn append<Item: Encode>(item: Item)
+		where
+			Self::Value: StorageAppend<Item>,
+		{
+			super::io_storage::append(Self::raw_storage_key(), &item.encode())
+		}
+
+		/// The number of items in the stored sequence, without decoding them.
+		///
+		/// `None` if there is no value, or it does not start with a valid length.
+		fn decode_len() -> Option<usize>
+		where
+			Self::Value: DecodeLength,
+		{
+			super::io_storage::read_with(Self::raw_storage_key(), decode_len::<Self::Value>)
 		}
 	}
 
@@ -472,24 +584,30 @@ pub mod shared {
 	/// This trait provides all the auto-implementation for a struct to become a storage map, via
 	/// implementing it.
 	///
-	/// The only method that must be implemented is `raw_storage_key`. This method will dictate
-	/// where should the final key of any item in this map be stored in the underlying storage (when
-	/// communicating with `io_storage` module).
+	/// The only methods that must be implemented are `module_prefix` and `storage_prefix`, along
+	/// with the `Hasher` used for keys. Together, they dictate where the final key of any item in
+	/// this map is stored in the underlying storage (when communicating with `io_storage` module):
+	/// the prefix of the map, followed by the hashed encoding of the key. See
+	/// [`StorageMap::raw_storage_key`].
 	///
 	/// ```
 	/// # use std::string::String;
-	/// # use pba_pre_course_assignment::l_mini_substrate::shared::StorageMap;
-	/// # use parity_scale_codec::Encode;
+	/// # use pba_pre_course_assignment::l_mini_substrate::shared::{
+	/// #     OptionQuery, StorageMap, Twox64Concat,
+	/// # };
 	///
 	/// /// A map from `u32` to `String`.
 	/// struct DummyStorageMap;
 	/// impl StorageMap for DummyStorageMap {
 	///     type Key = u32;
 	///     type Value = String;
-	///     fn raw_storage_key(key: Self::Key) -> Vec<u8> {
-	///         let mut base_key = b"dummy_storage_map".to_vec();
-	///         base_key.extend(key.encode());
-	///         base_key
+	///     type Hasher = Twox64Concat;
+	///     type QueryKind = OptionQuery;
+	///     fn module_prefix() -> &'static str {
+	///         "Dummy"
+	///     }
+	///     fn storage_prefix() -> &'static str {
+	///         "DummyStorageMap"
 	///     }
 	/// }
 	///
@@ -550,27 +668,73 @@ pub mod shared {
 		type Key: Encode + Clone;
 		/// The value type of the map.
 		type Value: Encode + Decode;
+		/// The hasher applied to the encoded keys of this map.
+		type Hasher: StorageHasher;
+		/// How missing values are presented, e.g. [`OptionQuery`] for `None`. See
+		/// [`QueryKindTrait`].
+		type QueryKind: QueryKindTrait<Self::Value>;
+
+		/// The name of the module that this map belongs to.
+		fn module_prefix() -> &'static str;
+
+		/// The name of this map, unique within its module.
+		fn storage_prefix() -> &'static str;
+
+		/// The prefix shared by the storage keys of all entries of this map:
+		/// `twox_128(module_prefix()) ++ twox_128(storage_prefix())`.
+		fn prefix() -> super::io_storage::Key {
+			storage_prefix(Self::module_prefix(), Self::storage_prefix())
+		}
 
-		/// The final storage key of the given `Self::key`.
-		fn raw_storage_key(key: Self::Key) -> super::io_storage::Key;
+		/// The final storage key of the given `Self::key`: `prefix() ++ Hasher::hash(encode(key))`.
+		fn raw_storage_key(key: Self::Key) -> super::io_storage::Key {
+			let mut raw_key = Self::prefix();
+			raw_key.extend_from_slice(Self::Hasher::hash(&key.encode()).as_ref());
+			raw_key
+		}
 
-		/// Get the value associated with `key`.
-		fn get(key: Self::Key) -> Option<Self::Value> {
-			let key = Self::raw_storage_key(key);
-			super::io_storage::get(key)
-				.and_then(|raw_value| <Self::Value as Decode>::decode(&mut &*raw_value).ok())
+		/// Recover the key out of the final storage key of an entry of this map.
+		///
+		/// Only possible with a [`ReversibleStorageHasher`]. Returns `None` if `raw_key` is not the
+		/// storage key of any entry of this map.
+		fn key_from_raw(raw_key: &[u8]) -> Option<Self::Key>
+		where
+			Self::Key: Decode,
+			Self::Hasher: ReversibleStorageHasher,
+		{
+			decode_hashed::<Self::Hasher, _>(raw_key.strip_prefix(Self::prefix().as_slice())?).ok()
 		}
 
-		/// Check if the value exists in storage.
+		/// Get the value associated with `key`, as presented by `Self::QueryKind` if it doesn't
+		/// exist, e.g. `None` for an [`OptionQuery`].
+		///
+		/// A value that cannot be decoded is presented as if it didn't exist, see
+		/// [`StorageMap::try_get`].
+		fn get(key: Self::Key) -> Query<Self::Value, Self::QueryKind> {
+			let value = super::io_storage::read_with(Self::raw_storage_key(key), decode_raw);
+			Self::QueryKind::from_optional_value(value)
+		}
+
+		/// Like [`StorageMap::get`], but a value that cannot be decoded is reported as
+		/// [`StorageError::Corrupt`].
+		fn try_get(key: Self::Key) -> Result<Query<Self::Value, Self::QueryKind>, StorageError> {
+			try_read(&Self::raw_storage_key(key)).map(Self::QueryKind::from_optional_value)
+		}
+
+		/// Check if the value exists in storage, without decoding it.
 		fn exists(key: Self::Key) -> bool {
-			// Could be implemented more efficiently, guess how, and why?
-			Self::get(key).is_some()
+			super::io_storage::exists(Self::raw_storage_key(key))
 		}
 
 		/// Set a new `value` into the storage associated with `key`.
+		///
+		/// The default value of a [`ValueQuery`] is not stored, but removes the value instead.
 		fn set(key: Self::Key, value: Self::Value) {
-			let key = Self::raw_storage_key(key);
-			super::io_storage::set(key, value.encode())
+			let value = normalize::<_, Self::QueryKind>(Some(value));
+			put_raw(
+				&Self::raw_storage_key(key),
+				value.map(|value| value.encode()),
+			)
 		}
 
 		/// Remove any value associated with `key` from the storage.
@@ -581,162 +745,938 @@ pub mod shared {
 
 		/// Mutate the value associated with `key` in place based on the given `f`.
 		///
-		/// `f` will see `None` if the value doesn't exist, and `Some` if it does.
+		/// `f` sees the value as `get` would return it, e.g. `None` for a value that doesn't exist
+		/// with an [`OptionQuery`], and the value is written back in the same way as `set` would.
+		/// For an [`OptionQuery`], this means that:
 		///
 		/// If the value doesn't exists, but `f` mutates the given `None` to `Some(_)`, it will be
 		/// created.
 		///
 		/// If the value exists, but it is mutated to `None`, it will be removed.
-		fn mutate(key: Self::Key, f: impl FnOnce(&mut Option<Self::Value>)) {
-			// todo!("provide an auto-implementation of this")
-			let storage_key = key.clone();
+		///
+		/// A value that cannot be decoded is given to `f` as missing, but is only overwritten if
+		/// `f` sets a new value, and never removed.
+		///
+		/// Returns whatever `f` returns.
+		fn mutate<R>(
+			key: Self::Key,
+			f: impl FnOnce(&mut Query<Self::Value, Self::QueryKind>) -> R,
+		) -> R {
+			infallible(Self::try_mutate(key, |value| Ok(f(value))))
+		}
+
+		/// Like [`StorageMap::mutate`], but `f` may fail, in which case nothing is written and the
+		/// error is returned.
+		fn try_mutate<R, E>(
+			key: Self::Key,
+			f: impl FnOnce(&mut Query<Self::Value, Self::QueryKind>) -> Result<R, E>,
+		) -> Result<R, E> {
+			Self::try_mutate_exists(key, |value| {
+				let mut query = Self::QueryKind::from_optional_value(value.take());
+				let result = f(&mut query)?;
+				*value = Self::QueryKind::into_optional_value(query);
+				Ok(result)
+			})
+		}
+
+		/// Mutate the value associated with `key`, where `f` sees `None` if the value does not
+		/// exist, and can remove it by setting it to `None`, regardless of `Self::QueryKind`.
+		fn mutate_exists<R>(key: Self::Key, f: impl FnOnce(&mut Option<Self::Value>) -> R) -> R {
+			infallible(Self::try_mutate_exists(key, |value| Ok(f(value))))
+		}
+
+		/// Like [`StorageMap::mutate_exists`], but `f` may fail, in which case nothing is written
+		/// and the error is returned.
+		fn try_mutate_exists<R, E>(
+			key: Self::Key,
+			f: impl FnOnce(&mut Option<Self::Value>) -> Result<R, E>,
+		) -> Result<R, E> {
+			try_mutate_raw::<_, Self::QueryKind, _, _>(&Self::raw_storage_key(key), f)
+		}
+
+		/// Remove the value associated with `key` from the storage, returning it as `get` would.
+		fn take(key: Self::Key) -> Query<Self::Value, Self::QueryKind> {
+			Self::QueryKind::from_optional_value(Self::mutate_exists(key, Option::take))
+		}
+
+		/// Add `item` at the end of the sequence associated with `key`, without decoding it, see
+		/// [`super::io_storage::append`]. A missing value is created with just `item` in it.
+		///
+		/// A stored value that does not start with a valid length is left untouched, and `item` is
+		/// dropped. Use [`StorageMap::try_get`] to find out whether the value is corrupt.
+		fn append<Item: Encode>(key: Self::Key, item: Item)
+		where
+			Self::Value: StorageAppend<Item>,
+		{
+			super::io_storage::append(Self::raw_storage_key(key), &item.encode())
+		}
+
+		/// The number of items in the sequence associated with `key`, without decoding them.
+		///
+		/// `None` if there is no value, or it does not start with a valid length.
+		fn decode_len(key: Self::Key) -> Option<usize>
+		where
+			Self::Value: DecodeLength,
+		{
+			super::io_storage::read_with(Self::raw_storage_key(key), decode_len::<Self::Value>)
+		}
+
+		/// Associate `value` with `key`, returning the value that was previously associated with
+		/// it, if any.
+		fn insert(key: Self::Key, value: Self::Value) -> Option<Self::Value> {
+			Self::mutate_exists(key, |old_value| old_value.replace(value))
+		}
+
+		/// Swap the values associated with `key1` and `key2`, whether they exist or not.
+		///
+		/// The raw values are swapped as they are, without being decoded.
+		fn swap(key1: Self::Key, key2: Self::Key) {
+			let (key1, key2) = (Self::raw_storage_key(key1), Self::raw_storage_key(key2));
+			let (value1, value2) = (super::io_storage::get(&key1), super::io_storage::get(&key2));
+			put_raw(&key1, value2);
+			put_raw(&key2, value1);
+		}
+
+		/// Iterate over all the entries of this map.
+		///
+		/// See [`StoragePrefixIterator`] for the order of the entries, and how undecodable ones are
+		/// reported. Only possible with a [`ReversibleStorageHasher`].
+		fn iter() -> StoragePrefixIterator<(Self::Key, Self::Value)>
+		where
+			Self::Key: Decode,
+			Self::Hasher: ReversibleStorageHasher,
+		{
+			StoragePrefixIterator::new(Self::prefix(), decode_entry::<Self::Hasher, _, _>)
+		}
+
+		/// Iterate over the keys of all the entries of this map, without decoding their values.
+		fn iter_keys() -> StoragePrefixIterator<Self::Key>
+		where
+			Self::Key: Decode,
+			Self::Hasher: ReversibleStorageHasher,
+		{
+			StoragePrefixIterator::new(Self::prefix(), |hashed, _| {
+				decode_hashed::<Self::Hasher, _>(hashed)
+			})
+		}
+
+		/// Iterate over the values of all the entries of this map, without decoding their keys.
+		///
+		/// Unlike [`StorageMap::iter`], this works with any hasher.
+		fn iter_values() -> StoragePrefixIterator<Self::Value> {
+			StoragePrefixIterator::new(Self::prefix(), |_, mut raw_value| {
+				Self::Value::decode_all(&mut raw_value)
+			})
+		}
 
-			let mut storage_value = Self::get(storage_key);
-			f(&mut storage_value);
+		/// Like [`StorageMap::iter`], but also remove each entry from the storage as soon as the
+		/// iterator gets to it, including the undecodable ones.
+		///
+		/// Entries that the iterator did not get to, e.g. because it was dropped early, are kept.
+		fn drain() -> StoragePrefixIterator<(Self::Key, Self::Value)>
+		where
+			Self::Key: Decode,
+			Self::Hasher: ReversibleStorageHasher,
+		{
+			Self::iter().draining()
+		}
 
-			match storage_value {
-				Some(new_value) => {
-					let storage_key = key.clone();
-					Self::set(storage_key, new_value)
+		/// Translate the values of all entries from their old type `O`, e.g. after the type of the
+		/// values of this map has changed. Entries for which `f` returns `None` are removed, and
+		/// the new values are normalized according to [`StorageMap::QueryKind`], as in any other
+		/// write.
+		///
+		/// Entries that cannot be decoded into `(Self::Key, O)` are left as they are, and all
+		/// reported at the end.
+		fn translate<O: Decode>(
+			mut f: impl FnMut(Self::Key, O) -> Option<Self::Value>,
+		) -> Result<(), Vec<StorageError>>
+		where
+			Self::Key: Decode,
+			Self::Hasher: ReversibleStorageHasher,
+		{
+			let mut errors = Vec::new();
+			let entries = StoragePrefixIterator::<(Self::Key, O)>::new(
+				Self::prefix(),
+				decode_entry::<Self::Hasher, _, _>,
+			);
+			for entry in entries {
+				match entry {
+					Ok((key, old_value)) => {
+						let raw_key = Self::raw_storage_key(key.clone());
+						let new_value = normalize::<_, Self::QueryKind>(f(key, old_value));
+						put_raw(&raw_key, new_value.map(|value| value.encode()));
+					}
+					Err(error) => errors.push(error),
 				}
-				None => Self::clear(key),
+			}
+
+			if errors.is_empty() {
+				Ok(())
+			} else {
+				Err(errors)
 			}
 		}
 	}
 
-	/// This is just a marker trait that wraps a bunch of other traits. It is meant to represent a
-	/// numeric type, like a balance, e.g. `u32`.
+	/// A [`StorageMap`] that also keeps track of its number of entries, such that
+	/// [`CountedStorageMap::count`] does not need to go through the whole map.
 	///
-	/// It helps us not repeat the long list of traits multiple times, and instead just have `type:
-	/// BalanceT`.
+	/// The count is stored in a companion [`StorageValue`], the `Counter`, and kept up to date by
+	/// the writes of this trait. Reads and iteration simply go to the underlying `Map`, but writes
+	/// that go to it directly do not update the count, which [`CountedStorageMap::check_count`]
+	/// can then detect.
 	///
-	/// The blanket implementation for such marker traits is interesting and a common pattern.
+	/// ```
+	/// # use pba_pre_course_assignment::l_mini_substrate::shared::{
+	/// #     CountedStorageMap, OptionQuery, StorageMap, StorageValue, Twox64Concat, ValueQuery,
+	/// # };
+	/// struct Names;
+	/// impl StorageMap for Names {
+	///     type Key = u32;
+	///     type Value = String;
+	///     type Hasher = Twox64Concat;
+	///     type QueryKind = OptionQuery;
+	///     fn module_prefix() -> &'static str {
+	///         "Dummy"
+	///     }
+	///     fn storage_prefix() -> &'static str {
+	///         "Names"
+	///     }
+	/// }
 	///
-	/// Note the usage of `CheckedSub` and `CheckedAdd`, this is how we perform "overflow-safe"
-	/// arithmetic.
+	/// struct NameCount;
+	/// impl StorageValue for NameCount {
+	///     type Value = u32;
+	///     type QueryKind = ValueQuery;
+	///     fn module_prefix() -> &'static str {
+	///         "Dummy"
+	///     }
+	///     fn storage_prefix() -> &'static str {
+	///         "NameCount"
+	///     }
+	/// }
 	///
-	/// TODO: some external resources would be good.
-	pub trait BalanceT:
-		Copy
-		+ Clone
-		+ Default
-		+ Encode
-		+ Decode
-		+ CheckedSub
-		+ CheckedAdd
-		+ Zero
-		+ Ord
-		+ PartialOrd
-		+ Eq
-		+ PartialEq
-		+ Debug
-	{
-	}
-	impl<
-			T: Copy
-				+ Clone
-				+ Default
-				+ Encode
-				+ Decode
-				+ CheckedSub
-				+ CheckedAdd
-				+ Ord
-				+ Zero
-				+ PartialOrd
-				+ Eq
-				+ PartialEq
-				+ Debug,
-		> BalanceT for T
-	{
-	}
-
-	/// A trait to represent basic functionality of a crypto-currency.
+	/// struct CountedNames;
+	/// impl CountedStorageMap for CountedNames {
+	///     type Map = Names;
+	///     type Counter = NameCount;
+	/// }
 	///
-	/// This should be implemented by `currency_module::Module`.
-	pub trait CryptoCurrency {
-		/// The numeric type used to represent balances.
-		type Balance: BalanceT;
+	/// fn main() {
+	///     CountedNames::set(1, "Alice".into());
+	///     CountedNames::set(2, "Bob".into());
+	///     // overwriting an entry does not count twice.
+	///     CountedNames::set(2, "Bobby".into());
+	///     assert_eq!(CountedNames::count(), 2);
+	///
+	///     CountedNames::clear(1);
+	///     assert_eq!(CountedNames::count(), 1);
+	///     assert_eq!(CountedNames::check_count(), Ok(1));
+	/// }
+	/// ```
+	pub trait CountedStorageMap {
+		/// The map whose entries are counted.
+		type Map: StorageMap;
+		/// Where the number of entries of `Map` is stored. An empty map has no count stored.
+		type Counter: StorageValue<Value = u32, QueryKind = ValueQuery>;
+
+		/// The number of entries in the map.
+		fn count() -> u32 {
+			Self::Counter::get()
+		}
 
-		/// Transfer `amount` from `from` to `to`.
-		fn transfer(from: AccountId, to: AccountId, amount: Self::Balance) -> DispatchResult;
+		/// Get the value associated with `key`.
+		fn get(
+			key: <Self::Map as StorageMap>::Key,
+		) -> Query<<Self::Map as StorageMap>::Value, <Self::Map as StorageMap>::QueryKind> {
+			Self::Map::get(key)
+		}
 
-		/// Reserve exactly `amount` from `from`.
-		fn reserve(from: AccountId, amount: Self::Balance) -> DispatchResult;
+		/// Check if the value exists in storage.
+		fn exists(key: <Self::Map as StorageMap>::Key) -> bool {
+			Self::Map::exists(key)
+		}
 
-		/// Get the free balance of a given account, `None` if not existent.
-		fn free_balance(of: AccountId) -> Option<Self::Balance>;
+		/// Set a new `value` into the storage associated with `key`, counting it if it is new.
+		///
+		/// Like [`StorageMap::set`], the default value of a [`ValueQuery`] map removes the entry,
+		/// and the count follows.
+		fn set(key: <Self::Map as StorageMap>::Key, value: <Self::Map as StorageMap>::Value) {
+			let existed = Self::Map::exists(key.clone());
+			Self::Map::set(key.clone(), value);
+			update_count::<Self::Counter>(existed, Self::Map::exists(key))
+		}
 
-		/// Get the reserved balance of a given account, `None` if non-existent.
-		fn reserved_balance(of: AccountId) -> Option<Self::Balance>;
-	}
-}
+		/// Remove any value associated with `key` from the storage, and from the count.
+		fn clear(key: <Self::Map as StorageMap>::Key) {
+			if Self::Map::exists(key.clone()) {
+				change_count::<Self::Counter>(false);
+				Self::Map::clear(key)
+			}
+		}
 
-/// The crypto-currency module.
-///
-/// It contains:
-///
-/// 1. [`currency_module::Config`]: a wrapper for configurations of this module that should come
-///        from the over-arching runtime.
-/// 2. [`currency_module::Module`]: a struct that will contain all the implementations, including
-///    the transactions, and the [`shared::CryptoCurrency`] trait.
-/// 3. [`currency_module::Call`]: The `Call` type for this module.
-///
-/// Among other things.
-///
-/// This module contains two storage items:
-///
-/// 1. [`currency_module::TotalIssuance`]: a `StorageValue` containing the sum of all balances in
-///    the system.
-/// 2. [`currency_module::BalancesMap`]: a `StorageMap` that maps from an account ID to their
-///    balance.
-pub mod currency_module {
-	use super::{
-		io_storage,
-		shared::{self, DispatchResult, Get, StorageValue, StorageMap},
-	};
-	use num::Zero;
-	use num::{CheckedAdd, CheckedSub};
-	use parity_scale_codec::{Decode, Encode};
+		/// Mutate the value associated with `key` in place based on the given `f`, updating the
+		/// count if an entry is created or removed. See [`StorageMap::mutate_exists`].
+		fn mutate<R>(
+			key: <Self::Map as StorageMap>::Key,
+			f: impl FnOnce(&mut Option<<Self::Map as StorageMap>::Value>) -> R,
+		) -> R {
+			infallible(Self::try_mutate(key, |value| Ok(f(value))))
+		}
 
-	/// Configurations of this module, coming from the outer world/runtime.
-	///
-	/// These are basically all the things that we don't want to make a concrete decision about, so
-	/// we let the outer world decide.
-	///
-	/// Within this module, we keep all implementation blocks generic over a `<T: Config>`, and use
-	/// the associated items as `T::MinimumBalance`, `T::Balance` etc.
-	///
-	/// This is a very common pattern in Substrate!
-	pub trait Config {
-		/// The identifier of this module.
-		const MODULE_ID: &'static str;
+		/// Like [`CountedStorageMap::mutate`], but `f` may fail, in which case nothing is written,
+		/// and the count stays the same.
+		fn try_mutate<R, E>(
+			key: <Self::Map as StorageMap>::Key,
+			f: impl FnOnce(&mut Option<<Self::Map as StorageMap>::Value>) -> Result<R, E>,
+		) -> Result<R, E> {
+			let existed = Self::Map::exists(key.clone());
+			let result = Self::Map::try_mutate_exists(key.clone(), f)?;
+			// what is stored may differ from what `f` left, e.g. the default of a `ValueQuery`.
+			update_count::<Self::Counter>(existed, Self::Map::exists(key));
+			Ok(result)
+		}
 
-		/// The account that is allowed to mint new tokens. Think: the admin.
-		type Minter: Get<shared::AccountId>;
+		/// Remove the value associated with `key` from the storage, and from the count, returning
+		/// it.
+		fn take(key: <Self::Map as StorageMap>::Key) -> Option<<Self::Map as StorageMap>::Value> {
+			Self::mutate(key, Option::take)
+		}
 
-		/// The minimum *free* balances (as explained in [`AccountBalance::free`]) that should be
-		/// held by ANY account at ANY POINT IN TIME.
+		/// Count the entries of the map one by one, regardless of whether they can be decoded.
 		///
-		/// An account with free balance less than this amount is considered a logical error.
-		type MinimumBalance: shared::Get<Self::Balance>;
+		/// This goes through the whole map, and is only meant to double check
+		/// [`CountedStorageMap::count`].
+		fn recount() -> u32 {
+			super::io_storage::iter_prefix(&Self::Map::prefix()).count() as u32
+		}
 
-		/// The numeric type that we use to store balances, e.g. `u64`.
-		type Balance: shared::BalanceT;
+		/// Check that the stored count matches the actual number of entries, returning it.
+		fn check_count() -> Result<u32, CountMismatch> {
+			let (stored, actual) = (Self::count(), Self::recount());
+			if stored != actual {
+				return Err(CountMismatch { stored, actual });
+			}
+			Ok(stored)
+		}
 	}
 
-	/// This module's `Call` enum.
+	/// The stored count of a [`CountedStorageMap`] does not match its actual number of entries.
+	#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+	pub struct CountMismatch {
+		/// The count, as stored in the counter.
+		pub stored: u32,
+		/// The actual number of entries in the map.
+		pub actual: u32,
+	}
+
+	/// Count an entry that now exists but did not before, or the other way around.
+	fn update_count<C>(existed: bool, exists: bool)
+	where
+		C: StorageValue<Value = u32, QueryKind = ValueQuery>,
+	{
+		if exists != existed {
+			change_count::<C>(exists)
+		}
+	}
+
+	/// Add one entry to the count in `C`, or remove one.
+	fn change_count<C: StorageValue<Value = u32, QueryKind = ValueQuery>>(increment: bool) {
+		C::mutate(|count| {
+			*count = if increment {
+				count.saturating_add(1)
+			} else {
+				count.saturating_sub(1)
+			};
+		})
+	}
+
+	/// Abstraction around a map with two keys stored in the storage, e.g. the allowance of a
+	/// spender on behalf of an owner.
 	///
-	/// Contains all of the operations, and possible arguments (except `sender`, of course).
-	pub enum Call<T: Config> {
-		/// Mint `amount` of tokens to `dest`. This will increase the total issuance of the system.
-		///
-		/// If `dest` exists, its balance is increased. Else, it is created.
+	/// Like [`StorageMap`], only `module_prefix` and `storage_prefix` must be implemented, along
+	/// with a hasher for each of the two keys. The final key of an entry is the prefix of the map,
+	/// followed by the hashed encoding of the first key, followed by the hashed encoding of the
+	/// second key. See [`StorageDoubleMap::raw_storage_key`].
+	///
+	/// All the entries that share the same first key are thus stored next to each other, under
+	/// [`StorageDoubleMap::prefix_for`], which is what makes [`StorageDoubleMap::iter_prefix`] and
+	/// [`StorageDoubleMap::clear_prefix`] possible.
+	///
+	/// ```
+	/// # use pba_pre_course_assignment::l_mini_substrate::shared::{
+	/// #     AccountId, Blake2_128Concat, StorageDoubleMap, Twox64Concat,
+	/// # };
+	/// /// How much each spender may spend on behalf of each owner.
+	/// struct Allowances;
+	/// impl StorageDoubleMap for Allowances {
+	///     type Key1 = AccountId;
+	///     type Key2 = AccountId;
+	///     type Value = u128;
+	///     type Hasher1 = Blake2_128Concat;
+	///     type Hasher2 = Twox64Concat;
+	///     fn module_prefix() -> &'static str {
+	///         "Dummy"
+	///     }
+	///     fn storage_prefix() -> &'static str {
+	///         "Allowances"
+	///     }
+	/// }
+	///
+	/// fn main() {
+	///     let (alice, bob, charlie) = (AccountId(1), AccountId(2), AccountId(3));
+	///     Allowances::set(alice, bob, 10);
+	///     Allowances::set(alice, charlie, 20);
+	///     Allowances::set(bob, alice, 30);
+	///     assert_eq!(Allowances::get(alice, bob), Some(10));
+	///     assert!(!Allowances::contains(bob, charlie));
+	///
+	///     // everything alice allowed, and only that.
+	///     let mut allowed = Allowances::iter_prefix(alice)
+	///         .collect::<Result<Vec<_>, _>>()
+	///         .unwrap();
+	///     allowed.sort_by_key(|(spender, _)| spender.0);
+	///     assert_eq!(allowed, vec![(bob, 10), (charlie, 20)]);
+	///
+	///     assert_eq!(Allowances::clear_prefix(alice), 2);
+	///     assert_eq!(Allowances::iter_prefix(alice).count(), 0);
+	///     assert_eq!(Allowances::get(bob, alice), Some(30));
+	/// }
+	/// ```
+	pub trait StorageDoubleMap {
+		/// The first key type of this map.
+		type Key1: Encode;
+		/// The second key type of this map.
+		type Key2: Encode;
+		/// The value type of the map.
+		type Value: Encode + Decode;
+		/// The hasher applied to the encoded first keys of this map.
+		type Hasher1: StorageHasher;
+		/// The hasher applied to the encoded second keys of this map.
+		type Hasher2: StorageHasher;
+
+		/// The name of the module that this map belongs to.
+		fn module_prefix() -> &'static str;
+
+		/// The name of this map, unique within its module.
+		fn storage_prefix() -> &'static str;
+
+		/// The prefix shared by the storage keys of all entries of this map:
+		/// `twox_128(module_prefix()) ++ twox_128(storage_prefix())`.
+		fn prefix() -> super::io_storage::Key {
+			storage_prefix(Self::module_prefix(), Self::storage_prefix())
+		}
+
+		/// The prefix shared by the storage keys of all entries under the first key `k1`:
+		/// `prefix() ++ Hasher1::hash(encode(k1))`.
+		fn prefix_for(k1: Self::Key1) -> super::io_storage::Key {
+			let mut raw_key = Self::prefix();
+			raw_key.extend_from_slice(Self::Hasher1::hash(&k1.encode()).as_ref());
+			raw_key
+		}
+
+		/// The final storage key of the entry under `(k1, k2)`:
+		/// `prefix_for(k1) ++ Hasher2::hash(encode(k2))`.
+		fn raw_storage_key(k1: Self::Key1, k2: Self::Key2) -> super::io_storage::Key {
+			let mut raw_key = Self::prefix_for(k1);
+			raw_key.extend_from_slice(Self::Hasher2::hash(&k2.encode()).as_ref());
+			raw_key
+		}
+
+		/// Get the value associated with `(k1, k2)`.
+		fn get(k1: Self::Key1, k2: Self::Key2) -> Option<Self::Value> {
+			super::io_storage::read_with(Self::raw_storage_key(k1, k2), decode_raw)
+		}
+
+		/// Check if a value is associated with `(k1, k2)`.
+		fn contains(k1: Self::Key1, k2: Self::Key2) -> bool {
+			super::io_storage::exists(Self::raw_storage_key(k1, k2))
+		}
+
+		/// Set a new `value` into the storage associated with `(k1, k2)`.
+		fn set(k1: Self::Key1, k2: Self::Key2, value: Self::Value) {
+			let key = Self::raw_storage_key(k1, k2);
+			super::io_storage::set(key, value.encode())
+		}
+
+		/// Remove any value associated with `(k1, k2)` from the storage.
+		fn clear(k1: Self::Key1, k2: Self::Key2) {
+			let key = Self::raw_storage_key(k1, k2);
+			super::io_storage::clear(key)
+		}
+
+		/// Mutate the value associated with `(k1, k2)` in place based on the given `f`.
+		///
+		/// Same as [`StorageMap::mutate`]: `None` stands for the absence of a value, both before
+		/// and after `f`.
+		fn mutate<R>(
+			k1: Self::Key1,
+			k2: Self::Key2,
+			f: impl FnOnce(&mut Option<Self::Value>) -> R,
+		) -> R {
+			let key = Self::raw_storage_key(k1, k2);
+			infallible(try_mutate_raw::<_, OptionQuery, _, _>(&key, |value| {
+				Ok(f(value))
+			}))
+		}
+
+		/// Iterate over all the `(k2, value)` pairs stored under the first key `k1`.
+		///
+		/// See [`StoragePrefixIterator`] for the order of the entries, and how undecodable ones are
+		/// reported. Only possible with a [`ReversibleStorageHasher`] for the second key.
+		fn iter_prefix(k1: Self::Key1) -> StoragePrefixIterator<(Self::Key2, Self::Value)>
+		where
+			Self::Key2: Decode,
+			Self::Hasher2: ReversibleStorageHasher,
+		{
+			StoragePrefixIterator::new(Self::prefix_for(k1), decode_entry::<Self::Hasher2, _, _>)
+		}
+
+		/// Remove all the entries stored under the first key `k1`, returning how many there were.
+		fn clear_prefix(k1: Self::Key1) -> u32 {
+			let (removed, _) = super::io_storage::clear_prefix(&Self::prefix_for(k1), None);
+			removed
+		}
+	}
+
+	/// Abstraction around a map with any number of keys stored in the storage, e.g. the approvals
+	/// of a delegate on behalf of an owner, for each asset.
+	///
+	/// The key of the map is a [`KeyGenerator`]: a single [`NMapKey`], or a tuple of them, each
+	/// with its own hasher. The final key of an entry is the prefix of the map, followed by the
+	/// hashed encoding of each part of its key, in order. See [`StorageNMap::raw_storage_key`].
+	///
+	/// All the entries that share the same leading parts are thus stored next to each other, such
+	/// that they can be iterated over, or removed, given only these leading parts. See
+	/// [`StorageNMap::iter_prefix`].
+	///
+	/// ```
+	/// # use pba_pre_course_assignment::l_mini_substrate::shared::{
+	/// #     AccountId, Blake2_128Concat, NMapKey, StorageNMap, Twox64Concat,
+	/// # };
+	/// /// How much each delegate may spend on behalf of each owner, for each asset.
+	/// struct Approvals;
+	/// impl StorageNMap for Approvals {
+	///     type Key = (
+	///         NMapKey<Blake2_128Concat, u32>,
+	///         NMapKey<Blake2_128Concat, AccountId>,
+	///         NMapKey<Twox64Concat, AccountId>,
+	///     );
+	///     type Value = u128;
+	///     fn module_prefix() -> &'static str {
+	///         "Dummy"
+	///     }
+	///     fn storage_prefix() -> &'static str {
+	///         "Approvals"
+	///     }
+	/// }
+	///
+	/// fn main() {
+	///     let (alice, bob, charlie) = (AccountId(1), AccountId(2), AccountId(3));
+	///     Approvals::set((0, alice, bob), 10);
+	///     Approvals::set((0, alice, charlie), 20);
+	///     Approvals::set((1, alice, bob), 30);
+	///     assert_eq!(Approvals::get((0, alice, bob)), Some(10));
+	///
+	///     // all approvals of alice for asset 0.
+	///     assert_eq!(Approvals::iter_prefix((0, alice)).count(), 2);
+	///     // all approvals for asset 1, of any owner.
+	///     let approvals = Approvals::iter_prefix((1,)).collect::<Vec<_>>();
+	///     assert_eq!(approvals, vec![Ok(((alice, bob), 30))]);
+	///
+	///     assert_eq!(Approvals::clear_prefix((0,)), 2);
+	///     assert!(!Approvals::contains((0, alice, bob)));
+	/// }
+	/// ```
+	pub trait StorageNMap {
+		/// The parts of the key of this map, each with its hasher.
+		type Key: KeyGenerator;
+		/// The value type of the map.
+		type Value: Encode + Decode;
+
+		/// The name of the module that this map belongs to.
+		fn module_prefix() -> &'static str;
+
+		/// The name of this map, unique within its module.
+		fn storage_prefix() -> &'static str;
+
+		/// The prefix shared by the storage keys of all entries of this map:
+		/// `twox_128(module_prefix()) ++ twox_128(storage_prefix())`.
+		fn prefix() -> super::io_storage::Key {
+			storage_prefix(Self::module_prefix(), Self::storage_prefix())
+		}
+
+		/// The prefix shared by the storage keys of all entries whose key starts with `partial`:
+		/// `prefix()` followed by the hashed encoding of each part of `partial`.
+		fn prefix_for<P>(partial: P) -> super::io_storage::Key
+		where
+			Self::Key: HasKeyPrefix<P>,
+		{
+			let mut raw_key = Self::prefix();
+			raw_key.extend(<Self::Key as HasKeyPrefix<P>>::prefix_key(partial));
+			raw_key
+		}
+
+		/// The final storage key of the entry under `key`: `prefix()` followed by the hashed
+		/// encoding of each part of `key`.
+		fn raw_storage_key(key: <Self::Key as KeyGenerator>::Key) -> super::io_storage::Key {
+			let mut raw_key = Self::prefix();
+			Self::Key::hash_key(key, &mut raw_key);
+			raw_key
+		}
+
+		/// Get the value associated with `key`.
+		fn get(key: <Self::Key as KeyGenerator>::Key) -> Option<Self::Value> {
+			super::io_storage::read_with(Self::raw_storage_key(key), decode_raw)
+		}
+
+		/// Check if a value is associated with `key`.
+		fn contains(key: <Self::Key as KeyGenerator>::Key) -> bool {
+			super::io_storage::exists(Self::raw_storage_key(key))
+		}
+
+		/// Set a new `value` into the storage associated with `key`.
+		fn set(key: <Self::Key as KeyGenerator>::Key, value: Self::Value) {
+			let key = Self::raw_storage_key(key);
+			super::io_storage::set(key, value.encode())
+		}
+
+		/// Remove any value associated with `key` from the storage.
+		fn clear(key: <Self::Key as KeyGenerator>::Key) {
+			let key = Self::raw_storage_key(key);
+			super::io_storage::clear(key)
+		}
+
+		/// Mutate the value associated with `key` in place based on the given `f`.
+		///
+		/// Same as [`StorageMap::mutate`]: `None` stands for the absence of a value, both before
+		/// and after `f`.
+		fn mutate<R>(
+			key: <Self::Key as KeyGenerator>::Key,
+			f: impl FnOnce(&mut Option<Self::Value>) -> R,
+		) -> R {
+			let key = Self::raw_storage_key(key);
+			infallible(try_mutate_raw::<_, OptionQuery, _, _>(&key, |value| {
+				Ok(f(value))
+			}))
+		}
+
+		/// Iterate over all the entries whose key starts with `partial`, along with the remaining
+		/// parts of their key.
+		///
+		/// See [`StoragePrefixIterator`] for the order of the entries, and how undecodable ones are
+		/// reported. Only possible if all the remaining parts use a [`ReversibleStorageHasher`].
+		fn iter_prefix<P>(partial: P) -> NMapPrefixIterator<Self, P>
+		where
+			Self::Key: HasKeyPrefix<P>,
+			<Self::Key as HasKeyPrefix<P>>::Suffix: ReversibleKeyGenerator,
+		{
+			StoragePrefixIterator::new(Self::prefix_for(partial), |suffix, mut raw_value| {
+				let key = <Self::Key as HasKeyPrefix<P>>::Suffix::decode_final_key(suffix)
+					.ok_or("invalid storage key")?;
+				Ok((key, Self::Value::decode_all(&mut raw_value)?))
+			})
+		}
+
+		/// Remove all the entries whose key starts with `partial`, returning how many there were.
+		fn clear_prefix<P>(partial: P) -> u32
+		where
+			Self::Key: HasKeyPrefix<P>,
+		{
+			let (removed, _) = super::io_storage::clear_prefix(&Self::prefix_for(partial), None);
+			removed
+		}
+	}
+
+	/// The iterator returned by [`StorageNMap::iter_prefix`], over the entries of the map `M` whose
+	/// key starts with `P`, along with the remaining parts of their key.
+	pub type NMapPrefixIterator<M, P> = StoragePrefixIterator<(
+		SuffixKey<<M as StorageNMap>::Key, P>,
+		<M as StorageNMap>::Value,
+	)>;
+
+	/// An iterator over the decoded entries of a storage item under some prefix, as returned by
+	/// e.g. [`StorageMap::iter`] or [`StorageDoubleMap::iter_prefix`].
+	///
+	/// Entries come in the lexicographic order of their storage keys, which only depends on the
+	/// keys themselves, not on when they were inserted. Like [`super::io_storage::iter_prefix`],
+	/// the iterator does not borrow the storage.
+	///
+	/// An entry whose key or value cannot be decoded is not skipped: it yields a
+	/// [`StorageError::Corrupt`], and the iteration goes on with the next entry.
+	pub struct StoragePrefixIterator<T> {
+		inner: super::io_storage::PrefixIterator,
+		prefix_len: usize,
+		/// Decode an item out of what follows the prefix in the storage key, and the raw value.
+		decode: fn(&[u8], &[u8]) -> Result<T, parity_scale_codec::Error>,
+		/// Whether to remove each entry from the storage once it is visited.
+		drain: bool,
+	}
+
+	impl<T> StoragePrefixIterator<T> {
+		fn new(
+			prefix: super::io_storage::Key,
+			decode: fn(&[u8], &[u8]) -> Result<T, parity_scale_codec::Error>,
+		) -> Self {
+			Self {
+				inner: super::io_storage::iter_prefix(&prefix),
+				prefix_len: prefix.len(),
+				decode,
+				drain: false,
+			}
+		}
+
+		fn draining(self) -> Self {
+			Self {
+				drain: true,
+				..self
+			}
+		}
+	}
+
+	impl<T> Iterator for StoragePrefixIterator<T> {
+		type Item = Result<T, StorageError>;
+
+		fn next(&mut self) -> Option<Self::Item> {
+			let (raw_key, raw_value) = self.inner.next()?;
+			if self.drain {
+				super::io_storage::clear(&raw_key);
+			}
+			let item = (self.decode)(&raw_key[self.prefix_len..], &raw_value);
+			Some(item.map_err(|error| StorageError::Corrupt {
+				key: raw_key,
+				error,
+			}))
+		}
+	}
+
+	/// Decode a key out of its hashed encoding, which must be `H::hash(encode(key))` and nothing
+	/// more.
+	fn decode_hashed<H, K>(hashed: &[u8]) -> Result<K, parity_scale_codec::Error>
+	where
+		H: ReversibleStorageHasher,
+		K: Encode + Decode,
+	{
+		let key = K::decode_all(&mut H::reverse(hashed).ok_or("storage key is too short")?)?;
+		// also make sure that the hash, and nothing else, precedes the key.
+		if H::hash(&key.encode()).as_ref() != hashed {
+			return Err("storage key does not match its hash".into());
+		}
+		Ok(key)
+	}
+
+	/// Decode an entry out of the hashed encoding of its key, see [`decode_hashed`], and its raw
+	/// value.
+	fn decode_entry<H, K, V>(
+		hashed: &[u8],
+		mut raw_value: &[u8],
+	) -> Result<(K, V), parity_scale_codec::Error>
+	where
+		H: ReversibleStorageHasher,
+		K: Encode + Decode,
+		V: Decode,
+	{
+		Ok((
+			decode_hashed::<H, K>(hashed)?,
+			V::decode_all(&mut raw_value)?,
+		))
+	}
+
+	/// Decode a raw value straight from storage, if any. Values that fail to decode, or that are
+	/// followed by extra bytes, are treated as absent.
+	fn decode_raw<V: Decode>(raw_value: Option<&[u8]>) -> Option<V> {
+		raw_value.and_then(|mut raw_value| V::decode_all(&mut raw_value).ok())
+	}
+
+	/// The number of items in a raw value straight from storage, if any, see [`DecodeLength`].
+	fn decode_len<V: DecodeLength>(raw_value: Option<&[u8]>) -> Option<usize> {
+		raw_value.and_then(|raw_value| V::decode_len(raw_value).ok())
+	}
+
+	/// Read and decode the value under `key`, if any. Unlike [`decode_raw`], values that fail to
+	/// decode are reported.
+	fn try_read<V: Decode>(key: &[u8]) -> Result<Option<V>, StorageError> {
+		let value = super::io_storage::read_with(key, |raw_value| {
+			raw_value
+				.map(|mut raw_value| V::decode_all(&mut raw_value))
+				.transpose()
+		});
+		value.map_err(|error| StorageError::Corrupt {
+			key: key.to_vec(),
+			error,
+		})
+	}
+
+	/// Decode the value under `key`, let `f` mutate it, and write it back as `Q` would store it,
+	/// unless `f` fails. A `None` stands for the absence of a value, both before and after `f`.
+	///
+	/// A value that cannot be decoded is given to `f` as `None`, and its bytes are only replaced
+	/// if `f` sets a new value. They are never removed.
+	fn try_mutate_raw<V: Encode + Decode, Q: QueryKindTrait<V>, R, E>(
+		key: &[u8],
+		f: impl FnOnce(&mut Option<V>) -> Result<R, E>,
+	) -> Result<R, E> {
+		let (mut storage_value, corrupt) = match try_read(key) {
+			Ok(storage_value) => (storage_value, false),
+			Err(StorageError::Corrupt { .. }) => (None, true),
+		};
+		let result = f(&mut storage_value)?;
+		let storage_value = normalize::<_, Q>(storage_value);
+		if corrupt && storage_value.is_none() {
+			// keep the corrupt bytes around, e.g. for `corrupt_storage` to find them.
+			return Ok(result);
+		}
+		put_raw(key, storage_value.map(|new_value| new_value.encode()));
+		Ok(result)
+	}
+
+	/// Set the raw value under `key`, or remove it if `None`.
+	fn put_raw(key: &[u8], raw_value: Option<super::io_storage::Value>) {
+		match raw_value {
+			Some(raw_value) => super::io_storage::set(key, raw_value),
+			None => super::io_storage::clear(key),
+		}
+	}
+
+	/// The value of a result that cannot be an error.
+	fn infallible<R>(result: Result<R, Infallible>) -> R {
+		match result {
+			Ok(result) => result,
+			Err(never) => match never {},
+		}
+	}
+
+	/// This is just a marker trait that wraps a bunch of other traits. It is meant to represent a
+	/// numeric type, like a balance, e.g. `u32`.
+	///
+	/// It helps us not repeat the long list of traits multiple times, and instead just have `type:
+	/// BalanceT`.
+	///
+	/// The blanket implementation for such marker traits is interesting and a common pattern.
+	///
+	/// Note the usage of `CheckedSub` and `CheckedAdd`, this is how we perform "overflow-safe"
+	/// arithmetic.
+	///
+	/// TODO: some external resources would be good.
+	pub trait BalanceT:
+		Copy
+		+ Clone
+		+ Default
+		+ Encode
+		+ Decode
+		+ CheckedSub
+		+ CheckedAdd
+		+ Zero
+		+ Ord
+		+ PartialOrd
+		+ Eq
+		+ PartialEq
+		+ Debug
+	{
+	}
+	impl<
+			T: Copy
+				+ Clone
+				+ Default
+				+ Encode
+				+ Decode
+				+ CheckedSub
+				+ CheckedAdd
+				+ Ord
+				+ Zero
+				+ PartialOrd
+				+ Eq
+				+ PartialEq
+				+ Debug,
+		> BalanceT for T
+	{
+	}
+
+	/// A trait to represent basic functionality of a crypto-currency.
+	///
+	/// This should be implemented by `currency_module::Module`.
+	pub trait CryptoCurrency {
+		/// The numeric type used to represent balances.
+		type Balance: BalanceT;
+
+		/// Transfer `amount` from `from` to `to`.
+		fn transfer(from: AccountId, to: AccountId, amount: Self::Balance) -> DispatchResult;
+
+		/// Reserve exactly `amount` from `from`.
+		fn reserve(from: AccountId, amount: Self::Balance) -> DispatchResult;
+
+		/// Get the free balance of a given account, `None` if not existent.
+		fn free_balance(of: AccountId) -> Option<Self::Balance>;
+
+		/// Get the reserved balance of a given account, `None` if non-existent.
+		fn reserved_balance(of: AccountId) -> Option<Self::Balance>;
+	}
+}
+
+/// The crypto-currency module.
+///
+/// It contains:
+///
+/// 1. [`currency_module::Config`]: a wrapper for configurations of this module that should come
+///    from the over-arching runtime.
+/// 2. [`currency_module::Module`]: a struct that will contain all the implementations, including
+///    the transactions, and the [`shared::CryptoCurrency`] trait.
+/// 3. [`currency_module::Call`]: The `Call` type for this module.
+///
+/// Among other things.
+///
+/// This module contains two storage items:
+///
+/// 1. [`currency_module::TotalIssuance`]: a `StorageValue` containing the sum of all balances in
+///    the system.
+/// 2. [`currency_module::BalancesMap`]: a `StorageMap` that maps from an account ID to their
+///    balance.
+pub mod currency_module {
+	use super::shared::{self, DispatchResult, Get, StorageMap, StorageValue};
+	use num::Zero;
+	use num::{CheckedAdd, CheckedSub};
+	use parity_scale_codec::{Decode, Encode};
+
+	/// Configurations of this module, coming from the outer world/runtime.
+	///
+	/// These are basically all the things that we don't want to make a concrete decision about, so
+	/// we let the outer world decide.
+	///
+	/// Within this module, we keep all implementation blocks generic over a `<T: Config>`, and use
+	/// the associated items as `T::MinimumBalance`, `T::Balance` etc.
+	///
+	/// This is a very common pattern in Substrate!
+	pub trait Config {
+		/// The identifier of this module.
+		const MODULE_ID: &'static str;
+
+		/// The account that is allowed to mint new tokens. Think: the admin.
+		type Minter: Get<shared::AccountId>;
+
+		/// The minimum *free* balances (as explained in [`AccountBalance::free`]) that should be
+		/// held by ANY account at ANY POINT IN TIME.
+		///
+		/// An account with free balance less than this amount is considered a logical error.
+		type MinimumBalance: shared::Get<Self::Balance>;
+
+		/// The numeric type that we use to store balances, e.g. `u64`.
+		type Balance: shared::BalanceT;
+	}
+
+	/// This module's `Call` enum.
+	///
+	/// Contains all of the operations, and possible arguments (except `sender`, of course).
+	pub enum Call<T: Config> {
+		/// Mint `amount` of tokens to `dest`. This will increase the total issuance of the system.
+		///
+		/// If `dest` exists, its balance is increased. Else, it is created.
 		///
 		/// ### Dispatch Errors
 		///
 		/// * [`Error::Overflow`] if any type of arithmetic operation overflows.
 		/// * [`Error::InsufficientFunds`] if the `dest`'s free balance will not be enough to pass
-		/// the bar of `T::MinimumBalance`.
+		///   the bar of `T::MinimumBalance`.
 		/// * [`Error::NotAllowed`] if the sender is not allowed to mint.
 		Mint {
 			dest: shared::AccountId,
@@ -853,6 +1793,14 @@ pub mod currency_module {
 
 	// NOTE: make sure to return correct [`Error`] types based on [`Call`] specifications.
 	impl<T: Config> AccountBalance<T> {
+		/// The balance of an account that is about to be created.
+		fn empty() -> Self {
+			Self {
+				free: Zero::zero(),
+				reserved: Zero::zero(),
+			}
+		}
+
 		/// Reserve `amount`, if possible.
 		fn reserve(&mut self, amount: T::Balance) -> shared::DispatchResult {
 			// todo!(
@@ -937,28 +1885,22 @@ pub mod currency_module {
 		}
 	}
 
-	/// A map from `AccountId` -> `AccountBalance`.
-	///
-	/// This is where the balance of each user should be stored.
-	pub struct BalancesMap<T: Config>(std::marker::PhantomData<T>);
-	impl<T: Config> shared::StorageMap for BalancesMap<T> {
-		type Key = shared::AccountId;
-		type Value = AccountBalance<T>;
-		fn raw_storage_key(key: Self::Key) -> io_storage::Key {
-			// todo!("determine storage key for BalancesMap based on the required specification")
-			[b"BalancesMap".as_ref(), &key.encode()].concat()
-		}
-	}
-
-	/// The total issuance. This should track be the sum of **free and reserved** balance of all
-	/// accounts, at all times.
-	pub struct TotalIssuance<T: Config>(std::marker::PhantomData<T>);
-	impl<T: Config> shared::StorageValue for TotalIssuance<T> {
-		type Value = T::Balance;
-		fn raw_storage_key() -> io_storage::Key {
-			// todo!("determine storage key for BalancesMap based on the required specification")
-			b"TotalIssuance".to_vec()
-		}
+	crate::storage! {
+		/// A map from `AccountId` -> `AccountBalance`.
+		///
+		/// This is where the balance of each user should be stored.
+		// accounts can be created by anyone, so their keys must be hashed securely. Whether an
+		// account exists matters, so missing balances are not presented as zero.
+		pub BalancesMap<T: Config> = map(
+			T::MODULE_ID,
+			shared::AccountId => AccountBalance<T>,
+			shared::Blake2_128Concat,
+			shared::OptionQuery,
+		);
+
+		/// The total issuance. This should track be the sum of **free and reserved** balance of
+		/// all accounts, at all times.
+		pub TotalIssuance<T: Config> = value(T::MODULE_ID, T::Balance, shared::ValueQuery);
 	}
 
 	/// Just a wrapper for this module's implementations.
@@ -971,48 +1913,24 @@ pub mod currency_module {
 
 		/// See [`Call::Transfer`].
 		fn transfer(
-			sender: shared::AccountId,
-			dest: shared::AccountId,
-			amount: T::Balance,
-		) -> shared::DispatchResult {
-			// todo!("complete this implementation based on the documentation above");
-			if !BalancesMap::<T>::exists(sender) {
-				Err(Error::<T>::DoesNotExist)?
-			}
-
-			let mut sender_account_balance = BalancesMap::<T>::get(sender).unwrap();
-			let can_transfer = sender_account_balance.can_transfer(amount.into());
-			match can_transfer {
-				Ok(_) => sender_account_balance.transfer(amount.into()).unwrap_or_default(),
-				Err(error) => return Err(error)
-			}
-
-			let mut dest_account_balance: AccountBalance<T> = AccountBalance {
-				free: amount.into(),  
-				reserved: Zero::zero(),
-			};
-
-			if BalancesMap::<T>::exists(dest) {
-				dest_account_balance = BalancesMap::<T>::get(dest).unwrap();
-				let can_receive = dest_account_balance.can_receive(amount.into());
-				match can_receive {
-					Ok(_) => dest_account_balance.receive(amount.into()).unwrap_or_default(),
-					Err(error) => return Err(error)
-				}
-			} else {
-				if amount < T::MinimumBalance::get() {
-					return Err(Error::<T>::InsufficientFunds)?
-				}
-			}
-
-			BalancesMap::mutate(sender, |sender_balance| {
-				*sender_balance = Some(sender_account_balance);
-			});
-			BalancesMap::mutate(dest, |dest_balance| {
-				*dest_balance = Some(dest_account_balance);
-			});
-
-			Ok(())
+			sender: shared::AccountId,
+			dest: shared::AccountId,
+			amount: T::Balance,
+		) -> shared::DispatchResult {
+			// the sender is debited first, such that a transfer to oneself is a no-op.
+			super::io_storage::with_transaction(|| {
+				BalancesMap::<T>::try_mutate(sender, |balance| {
+					balance
+						.as_mut()
+						.ok_or(Error::<T>::DoesNotExist)?
+						.transfer(amount)
+				})?;
+				BalancesMap::<T>::try_mutate(dest, |balance| {
+					balance
+						.get_or_insert_with(AccountBalance::empty)
+						.receive(amount)
+				})
+			})
 		}
 
 		/// See [`Call::TransferAll`].
@@ -1020,37 +1938,20 @@ pub mod currency_module {
 			sender: shared::AccountId,
 			dest: shared::AccountId,
 		) -> shared::DispatchResult {
-			// todo!("complete this implementation based on the documentation above");
-			if !BalancesMap::<T>::exists(sender) {
-				Err(Error::<T>::DoesNotExist)?
-			}
-
-			let mut sender_account_balance = BalancesMap::<T>::get(sender).unwrap();
-			let can_transfer = sender_account_balance.can_transfer(sender_account_balance.free.into());
-			match can_transfer {
-				Ok(_) => sender_account_balance.transfer(sender_account_balance.free.into()).unwrap_or_default(),
-				Err(error) => return Err(error)
-			}
-
-			if !BalancesMap::<T>::exists(dest) {
-				Err(Error::<T>::DoesNotExist)?
-			}
-
-			let mut dest_account_balance = BalancesMap::<T>::get(dest).unwrap();
-			let can_receive = dest_account_balance.can_receive(dest_account_balance.free.into());
-			match can_receive {
-				Ok(_) => dest_account_balance.receive(dest_account_balance.free.into()).unwrap_or_default(),
-				Err(error) => return Err(error)
-			}
-
-			BalancesMap::mutate(sender, |sender_balance| {
-				*sender_balance = Some(sender_account_balance);
-			});
-			BalancesMap::mutate(dest, |dest_balance| {
-				*dest_balance = Some(dest_account_balance);
-			});
-
-			Ok(())
+			super::io_storage::with_transaction(|| {
+				let amount = BalancesMap::<T>::try_mutate_exists(sender, |balance| {
+					let sender_balance = balance.take().ok_or(Error::<T>::DoesNotExist)?;
+					if !sender_balance.reserved.is_zero() {
+						Err(Error::<T>::NotAllowed)?
+					}
+					Ok::<_, shared::DispatchError>(sender_balance.free)
+				})?;
+				BalancesMap::<T>::try_mutate(dest, |balance| {
+					balance
+						.get_or_insert_with(AccountBalance::empty)
+						.receive(amount)
+				})
+			})
 		}
 
 		/// See [`Call::Mint`].
@@ -1059,25 +1960,21 @@ pub mod currency_module {
 			who: shared::AccountId,
 			amount: T::Balance,
 		) -> shared::DispatchResult {
-			// todo!("complete this implementation based on the documentation above");
 			if sender != T::Minter::get() {
 				Err(Error::<T>::NotAllowed)?
 			}
 
-			if !BalancesMap::<T>::exists(who) {
-				TotalIssuance::<T>::set(amount.clone());
-
-				let new_account_balance: AccountBalance<T> = AccountBalance {
-					free: amount.into(),  
-					reserved: Zero::zero(),
-				};
-
-				BalancesMap::mutate(who, |account_balance| {
-					*account_balance = Some(new_account_balance);
-				});
-			} 
-
-			Ok(())
+			super::io_storage::with_transaction(|| {
+				BalancesMap::<T>::try_mutate(who, |balance| {
+					balance
+						.get_or_insert_with(AccountBalance::empty)
+						.receive(amount)
+				})?;
+				TotalIssuance::<T>::try_mutate(|issuance| {
+					*issuance = issuance.checked_add(&amount).ok_or(Error::<T>::Overflow)?;
+					Ok(())
+				})
+			})
 		}
 
 		// NOTE: This is not reflected in [`Call`], so we document it here.
@@ -1092,19 +1989,12 @@ pub mod currency_module {
 		///   this operation. Recall that an accounts free balance must always remain equal or above
 		///   `T::MinimumBalance`.
 		pub fn reserve(from: shared::AccountId, amount: T::Balance) -> shared::DispatchResult {
-			// todo!("complete this implementation based on the documentation above");
-			if !BalancesMap::<T>::exists(from) {
-				Err(Error::<T>::DoesNotExist)?
-			}
-
-			let mut reserve_account_balance = BalancesMap::<T>::get(from).unwrap();
-			reserve_account_balance.reserve(amount.into()).unwrap_or_default();
-
-			BalancesMap::mutate(from, |reserve_balance| {
-				*reserve_balance = Some(reserve_account_balance);
-			});
-
-			Ok(())
+			BalancesMap::<T>::try_mutate(from, |balance| {
+				balance
+					.as_mut()
+					.ok_or(Error::<T>::DoesNotExist)?
+					.reserve(amount)
+			})
 		}
 
 		/// Unreserve exactly `amount` from `from`'s reserved balance, returning it back
@@ -1117,19 +2007,12 @@ pub mod currency_module {
 		/// * [`Error::InsufficientFunds`] if the account does not have enough reserved funds to
 		///   preform this operation.
 		pub fn unreserve(from: shared::AccountId, amount: T::Balance) -> shared::DispatchResult {
-			// todo!("complete this implementation based on the documentation above");
-			if !BalancesMap::<T>::exists(from) {
-				Err(Error::<T>::DoesNotExist)?
-			}
-
-			let mut unreserve_account_balance = BalancesMap::<T>::get(from).unwrap();
-			unreserve_account_balance.unreserve(amount.into()).unwrap_or_default();
-
-			BalancesMap::mutate(from, |runeserve_balance| {
-				*runeserve_balance = Some(unreserve_account_balance);
-			});
-
-			Ok(())
+			BalancesMap::<T>::try_mutate(from, |balance| {
+				balance
+					.as_mut()
+					.ok_or(Error::<T>::DoesNotExist)?
+					.unreserve(amount)
+			})
 		}
 	}
 
@@ -1159,13 +2042,11 @@ pub mod currency_module {
 		}
 
 		fn free_balance(of: shared::AccountId) -> Option<Self::Balance> {
-			// todo!("complete this implementation");
-			Some(BalancesMap::<T>::get(of).unwrap().free)
+			BalancesMap::<T>::get(of).map(|balance| balance.free)
 		}
 
 		fn reserved_balance(of: shared::AccountId) -> Option<Self::Balance> {
-			// todo!("complete this implementation");
-			Some(BalancesMap::<T>::get(of).unwrap().reserved)
+			BalancesMap::<T>::get(of).map(|balance| balance.reserved)
 		}
 	}
 }
@@ -1185,7 +2066,7 @@ pub mod currency_module {
 /// This module has no storage or error of itself, it entire relies on something else that
 /// implements [`shared::CryptoCurrency`], see [`staking_module::Config::Currency`].
 pub mod staking_module {
-	use super::{*, shared::StorageMap};
+	use super::{shared::StorageMap, *};
 
 	/// The configuration trait for this module.
 	pub trait Config {
@@ -1238,7 +2119,12 @@ pub mod staking_module {
 ///    configurable `type` items in each `Config` trait are!
 /// 2. Create an outer `RuntimeCall` and implement [`shared::Dispatchable`] for it.
 pub mod runtime {
-	use super::shared::{AccountId, Dispatchable, Get};
+	use super::{
+		currency_module::{BalancesMap, TotalIssuance},
+		registry::{StorageItem, StorageRegistry},
+		shared::{AccountId, Dispatchable, Get, StorageError, StorageMap, StorageValue},
+	};
+	use parity_scale_codec::Decode;
 
 	/// This is the runtime struct that will fulfill the `Config` trait of all the modules.
 	///
@@ -1283,12 +2169,69 @@ pub mod runtime {
 
 	impl Dispatchable for RuntimeCall {
 		fn dispatch(self, sender: AccountId) -> super::shared::DispatchResult {
-			// todo!("complete this implementation");
-			match self {
-				RuntimeCall::Currency(value) => super::shared::Dispatchable::dispatch(value, sender),
+			// Every dispatch is metered on its own, and runs in its own transaction, so that a failing
+			// call never leaves any partial state behind.
+			super::io_storage::reset_dispatch_stats();
+			super::io_storage::with_transaction(|| match self {
+				RuntimeCall::Currency(value) => {
+					super::shared::Dispatchable::dispatch(value, sender)
+				}
 				RuntimeCall::Staking(value) => super::shared::Dispatchable::dispatch(value, sender),
-			}
+			})
+		}
+	}
+
+	/// All the storage items of this runtime.
+	pub fn storage_registry() -> StorageRegistry {
+		StorageRegistry::default()
+			.with(StorageItem::value::<TotalIssuance<MyRuntime>>())
+			.with(StorageItem::map::<BalancesMap<MyRuntime>>())
+	}
+
+	/// Sanity checks of the runtime, meant to be run once at startup.
+	///
+	/// Panics if the storage keys of any two storage items overlap.
+	pub fn integrity_test() {
+		if let Err(collision) = storage_registry().check() {
+			panic!("invalid storage layout: {}", collision);
+		}
+	}
+
+	/// Every entry of the storage items of this runtime, as listed in [`storage_registry`], that
+	/// cannot be decoded.
+	///
+	/// This goes through the whole storage of every item, and is meant to be run on demand, e.g.
+	/// after a migration, rather than as part of a dispatch.
+	pub fn corrupt_storage() -> Vec<StorageError> {
+		let issuance = TotalIssuance::<MyRuntime>::try_get().err();
+		let balances = BalancesMap::<MyRuntime>::iter().filter_map(Result::err);
+		issuance.into_iter().chain(balances).collect()
+	}
+
+	/// A human readable `(key, value)` form of a raw storage entry, if it belongs to one of the
+	/// storage items of this runtime.
+	///
+	/// Meant to be used with [`super::io_storage::StateDiff::render`].
+	pub fn describe_storage_entry(key: &[u8], value: &[u8]) -> Option<(String, String)> {
+		if key == TotalIssuance::<MyRuntime>::raw_storage_key() {
+			let issuance =
+				<TotalIssuance<MyRuntime> as StorageValue>::Value::decode(&mut &*value).ok()?;
+			return Some(("TotalIssuance".to_string(), format!("{:?}", issuance)));
 		}
+
+		if let Some(account) = BalancesMap::<MyRuntime>::key_from_raw(key) {
+			let balance =
+				<BalancesMap<MyRuntime> as StorageMap>::Value::decode(&mut &*value).ok()?;
+			return Some((
+				format!("BalancesMap[{:?}]", account),
+				format!(
+					"AccountBalance {{ free: {:?}, reserved: {:?} }}",
+					balance.free, balance.reserved
+				),
+			));
+		}
+
+		None
 	}
 }
 
@@ -1315,95 +2258,244 @@ mod tests {
 		shared::*,
 	};
 
+	// A fresh, empty storage, isolated from every other test.
+	fn new_test_ext() -> io_storage::TestExternalities {
+		runtime::integrity_test();
+		io_storage::TestExternalities::default()
+	}
+
 	// shared setup for all tests. All we do for now is create one funded account '7'. Feel free to
 	// change this function based on the rest of your tests.
-	fn setup() {
-		let minter = shared::AccountId(42);
-		let dest = shared::AccountId(7);
-		let amount = 100;
-		currency_module::Call::<MyRuntime>::Mint { dest, amount }
-			.dispatch(minter)
-			.unwrap();
+	fn setup() -> io_storage::TestExternalities {
+		let mut ext = new_test_ext();
+		ext.execute_with(|| {
+			let minter = shared::AccountId(42);
+			let dest = shared::AccountId(7);
+			let amount = 100;
+			currency_module::Call::<MyRuntime>::Mint { dest, amount }
+				.dispatch(minter)
+				.unwrap();
+		});
+		ext
 	}
 
 	mod currency_tests {
 		use super::*;
-		use currency_module::{BalancesMap, Call, TotalIssuance};
+		use currency_module::{BalancesMap, Call, Module, TotalIssuance};
 
 		#[test]
 		fn storage_encoding() {
+			use hashing::{blake2_128, twox_128};
+			let module = twox_128(b"MOD_CURRENCY");
 			assert_eq!(
 				TotalIssuance::<MyRuntime>::raw_storage_key(),
-				b"TotalIssuance"
+				[module, twox_128(b"TotalIssuance")].concat()
 			);
+			let encoded_account = [42u8, 0, 0, 0];
 			assert_eq!(
 				BalancesMap::<MyRuntime>::raw_storage_key(AccountId(42)),
-				[b"BalancesMap".as_ref(), &[42u8, 0, 0, 0]].concat()
+				[
+					&module[..],
+					&twox_128(b"BalancesMap"),
+					&blake2_128(&encoded_account),
+					&encoded_account,
+				]
+				.concat()
 			);
 		}
 
 		#[test]
-		fn transfer_works() {
-			let minter = AccountId(42);
-			let alice = AccountId(7);
-			assert_eq!(TotalIssuance::<MyRuntime>::get().unwrap_or_default(), 0);
-
-			assert!(Call::<MyRuntime>::Mint {
-				dest: alice,
-				amount: 100
-			}
-			.dispatch(minter)
-			.is_ok());
-			assert_eq!(TotalIssuance::<MyRuntime>::get().unwrap_or_default(), 100);
-
-			// transfer 20 to 10
-			assert!(Call::<MyRuntime>::Transfer {
-				dest: AccountId(10),
-				amount: 20
-			}
-			.dispatch(alice)
-			.is_ok());
+		fn storage_keys_are_reversible() {
+			let raw_key = BalancesMap::<MyRuntime>::raw_storage_key(AccountId(42));
 			assert_eq!(
-				BalancesMap::<MyRuntime>::get(alice)
-					.map(|b| b.free)
-					.unwrap_or_default(),
-				80
+				BalancesMap::<MyRuntime>::key_from_raw(&raw_key),
+				Some(AccountId(42))
 			);
+
+			// other prefixes, tampered hashes, and trailing bytes are all rejected.
 			assert_eq!(
-				BalancesMap::<MyRuntime>::get(AccountId(10))
-					.map(|b| b.free)
-					.unwrap_or_default(),
-				20
+				BalancesMap::<MyRuntime>::key_from_raw(b"TotalIssuance"),
+				None
 			);
-			assert_eq!(TotalIssuance::<MyRuntime>::get().unwrap_or_default(), 100);
+			let mut tampered = raw_key.clone();
+			tampered[BalancesMap::<MyRuntime>::prefix().len()] ^= 1;
+			assert_eq!(BalancesMap::<MyRuntime>::key_from_raw(&tampered), None);
+			let longer = [raw_key.as_slice(), &[0]].concat();
+			assert_eq!(BalancesMap::<MyRuntime>::key_from_raw(&longer), None);
+		}
 
-			// alice cannot transfer more than she has.
-			assert_eq!(
-				Call::<MyRuntime>::Transfer {
+		#[test]
+		fn transfer_works() {
+			new_test_ext().execute_with(|| {
+				let minter = AccountId(42);
+				let alice = AccountId(7);
+				assert_eq!(TotalIssuance::<MyRuntime>::get(), 0);
+
+				assert!(Call::<MyRuntime>::Mint {
+					dest: alice,
+					amount: 100
+				}
+				.dispatch(minter)
+				.is_ok());
+				assert_eq!(TotalIssuance::<MyRuntime>::get(), 100);
+
+				// transfer 20 to 10
+				assert!(Call::<MyRuntime>::Transfer {
 					dest: AccountId(10),
-					amount: 90
+					amount: 20
 				}
 				.dispatch(alice)
-				.unwrap_err(),
-				DispatchError::Module {
+				.is_ok());
+				assert_eq!(
+					BalancesMap::<MyRuntime>::get(alice)
+						.map(|b| b.free)
+						.unwrap_or_default(),
+					80
+				);
+				assert_eq!(
+					BalancesMap::<MyRuntime>::get(AccountId(10))
+						.map(|b| b.free)
+						.unwrap_or_default(),
+					20
+				);
+				assert_eq!(TotalIssuance::<MyRuntime>::get(), 100);
+
+				// alice cannot transfer more than she has.
+				assert_eq!(
+					Call::<MyRuntime>::Transfer {
+						dest: AccountId(10),
+						amount: 90
+					}
+					.dispatch(alice)
+					.unwrap_err(),
+					DispatchError::Module {
+						module_id: "MOD_CURRENCY",
+						reason: "InsufficientFunds".to_string()
+					}
+				);
+
+				// alice cannot transfer less than 10 to a new account.
+				assert_eq!(
+					Call::<MyRuntime>::Transfer {
+						dest: AccountId(11),
+						amount: 3
+					}
+					.dispatch(alice)
+					.unwrap_err(),
+					DispatchError::Module {
+						module_id: "MOD_CURRENCY",
+						reason: "InsufficientFunds".to_string()
+					}
+				);
+			});
+		}
+
+		#[test]
+		fn failed_operations_change_nothing() {
+			setup().execute_with(|| {
+				let alice = AccountId(7);
+				let root = io_storage::storage_root();
+				let insufficient = DispatchError::Module {
 					module_id: "MOD_CURRENCY",
-					reason: "InsufficientFunds".to_string()
-				}
-			);
+					reason: "InsufficientFunds".to_string(),
+				};
 
-			// alice cannot transfer less than 10 to a new account.
-			assert_eq!(
+				assert_eq!(
+					Module::<MyRuntime>::reserve(alice, 99),
+					Err(insufficient.clone())
+				);
+				assert_eq!(
+					Module::<MyRuntime>::unreserve(alice, 1),
+					Err(insufficient.clone())
+				);
+				// the sender is checked before the recipient is created.
+				assert_eq!(
+					<Module<MyRuntime> as CryptoCurrency>::transfer(alice, AccountId(8), 200),
+					Err(insufficient)
+				);
+				assert_eq!(
+					Module::<MyRuntime>::reserve(AccountId(8), 1).unwrap_err(),
+					DispatchError::Module {
+						module_id: "MOD_CURRENCY",
+						reason: "DoesNotExist".to_string()
+					}
+				);
+				assert_eq!(io_storage::storage_root(), root);
+			});
+		}
+
+		#[test]
+		fn transfer_to_self_is_a_noop() {
+			setup().execute_with(|| {
+				let alice = AccountId(7);
 				Call::<MyRuntime>::Transfer {
-					dest: AccountId(11),
-					amount: 3
+					dest: alice,
+					amount: 50,
 				}
 				.dispatch(alice)
-				.unwrap_err(),
-				DispatchError::Module {
-					module_id: "MOD_CURRENCY",
-					reason: "InsufficientFunds".to_string()
+				.unwrap();
+				assert_eq!(BalancesMap::<MyRuntime>::get(alice).unwrap().free, 100);
+			});
+		}
+
+		#[test]
+		fn mint_adds_to_existing_balances() {
+			setup().execute_with(|| {
+				let alice = AccountId(7);
+				Call::<MyRuntime>::Mint {
+					dest: alice,
+					amount: 50,
 				}
-			);
+				.dispatch(AccountId(42))
+				.unwrap();
+				Call::<MyRuntime>::Mint {
+					dest: AccountId(8),
+					amount: 20,
+				}
+				.dispatch(AccountId(42))
+				.unwrap();
+				assert_eq!(BalancesMap::<MyRuntime>::get(alice).unwrap().free, 150);
+				assert_eq!(TotalIssuance::<MyRuntime>::get(), 170);
+			});
+		}
+
+		#[test]
+		fn transfer_all_removes_the_sender() {
+			setup().execute_with(|| {
+				let (alice, bob) = (AccountId(7), AccountId(8));
+				Module::<MyRuntime>::reserve(alice, 10).unwrap();
+				assert_eq!(
+					Call::<MyRuntime>::TransferAll { dest: bob }
+						.dispatch(alice)
+						.unwrap_err(),
+					DispatchError::Module {
+						module_id: "MOD_CURRENCY",
+						reason: "NotAllowed".to_string()
+					}
+				);
+
+				Module::<MyRuntime>::unreserve(alice, 10).unwrap();
+				Call::<MyRuntime>::TransferAll { dest: bob }
+					.dispatch(alice)
+					.unwrap();
+				assert!(!BalancesMap::<MyRuntime>::exists(alice));
+				assert_eq!(BalancesMap::<MyRuntime>::get(bob).unwrap().free, 100);
+			});
+		}
+
+		#[test]
+		fn balances_can_be_listed() {
+			setup().execute_with(|| {
+				Module::<MyRuntime>::transfer(AccountId(7), AccountId(8), 30).unwrap();
+				Module::<MyRuntime>::transfer(AccountId(7), AccountId(9), 20).unwrap();
+
+				let mut balances = BalancesMap::<MyRuntime>::iter()
+					.map(|entry| entry.map(|(who, balance)| (who.0, balance.free)))
+					.collect::<Result<Vec<_>, _>>()
+					.unwrap();
+				balances.sort();
+				assert_eq!(balances, vec![(7, 50), (8, 30), (9, 20)]);
+			});
 		}
 	}
 
@@ -1412,65 +2504,740 @@ mod tests {
 
 		#[test]
 		fn bonding_works() {
-			setup();
-			let alice = AccountId(7);
-			let amount = 50;
+			setup().execute_with(|| {
+				let alice = AccountId(7);
+				let amount = 50;
+
+				// Notice how `MyRuntime as staking_module::Config` is an equivalent type to
+				// `currency_module::Module<MyRuntime>`.
+				assert_eq!(
+					<MyRuntime as staking_module::Config>::Currency::reserved_balance(alice),
+					Some(0)
+				);
+				assert_eq!(
+					currency_module::Module::<MyRuntime>::reserved_balance(alice),
+					Some(0)
+				);
+
+				// Unlike in production code, an unwrap is perfectly fine here.
+				staking_module::Call::<MyRuntime>::Bond { amount }
+					.dispatch(alice)
+					.unwrap();
+
+				assert_eq!(
+					<MyRuntime as staking_module::Config>::Currency::free_balance(alice),
+					Some(50)
+				);
+				assert_eq!(
+					<MyRuntime as staking_module::Config>::Currency::reserved_balance(alice),
+					Some(50)
+				);
+			});
+		}
+	}
+
+	mod storage_tests {
+		use super::*;
+
+		crate::storage! {
+			Accounts = map("Test", u32 => u32, Blake2_128Concat, OptionQuery);
+			Nominations = map("Test", u32 => Vec<u32>, Twox64Concat, ValueQuery);
+			Nonces = map("Test", u32 => u32, Twox64Concat, ValueQuery);
+			Owner = value("Test", AccountId, ResultQuery<&'static str, NoOwner>);
+			AccountCount = value("Test", u32, ValueQuery);
+			NonceCount = value("Test", u32, ValueQuery);
+		}
+
+		#[test]
+		fn map_iteration_is_ordered_by_storage_key() {
+			let insert = |keys: &mut dyn Iterator<Item = u32>| {
+				let mut ext = new_test_ext();
+				ext.execute_with(|| {
+					keys.for_each(|k| Accounts::set(k, k * 10));
+					Accounts::iter().collect::<Result<Vec<_>, _>>().unwrap()
+				})
+			};
+			let entries = insert(&mut (0..20));
+			// the order does not depend on the order of insertion..
+			assert_eq!(insert(&mut (0..20).rev()), entries);
+			// ..but only on the storage keys.
+			let mut sorted = entries.clone();
+			sorted.sort_by_key(|(k, _)| Accounts::raw_storage_key(*k));
+			assert_eq!(sorted, entries);
+			assert_eq!(entries.len(), 20);
+
+			new_test_ext().execute_with(|| {
+				(0..20).for_each(|k| Accounts::set(k, k * 10));
+				let keys = Accounts::iter_keys()
+					.collect::<Result<Vec<_>, _>>()
+					.unwrap();
+				let values = Accounts::iter_values()
+					.collect::<Result<Vec<_>, _>>()
+					.unwrap();
+				assert_eq!(keys.into_iter().zip(values).collect::<Vec<_>>(), entries);
+			});
+		}
+
+		#[test]
+		fn map_iteration_reports_corrupt_entries() {
+			new_test_ext().execute_with(|| {
+				Accounts::set(1, 10);
+				Accounts::set(2, 20);
+				// a value that is too short, and a key that does not match its hash.
+				io_storage::set(Accounts::raw_storage_key(3), vec![1]);
+				let mut bad_key = Accounts::raw_storage_key(4);
+				*bad_key.last_mut().unwrap() = 5;
+				io_storage::set(&bad_key, 40u32.encode());
+
+				let corrupt = |entries: Vec<Result<_, StorageError>>| {
+					let mut keys = entries
+						.into_iter()
+						.filter_map(|entry| match entry {
+							Err(StorageError::Corrupt { key, .. }) => Some(key),
+							Ok(_) => None,
+						})
+						.collect::<Vec<_>>();
+					keys.sort();
+					keys
+				};
+				let mut both = vec![Accounts::raw_storage_key(3), bad_key.clone()];
+				both.sort();
+				assert_eq!(
+					corrupt(Accounts::iter().map(|e| e.map(drop)).collect()),
+					both
+				);
+				let keys = Accounts::iter_keys().map(|e| e.map(drop)).collect();
+				assert_eq!(corrupt(keys), [bad_key]);
+				assert_eq!(
+					corrupt(Accounts::iter_values().map(|e| e.map(drop)).collect()),
+					[Accounts::raw_storage_key(3)]
+				);
+				assert_eq!(Accounts::iter().filter(Result::is_ok).count(), 2);
+			});
+		}
+
+		#[test]
+		fn map_drain_removes_visited_entries() {
+			new_test_ext().execute_with(|| {
+				(0..10).for_each(|k| Accounts::set(k, k));
+
+				let first = Accounts::drain()
+					.take(3)
+					.collect::<Result<Vec<_>, _>>()
+					.unwrap();
+				assert_eq!(first.len(), 3);
+				assert!(first.iter().all(|(k, _)| !Accounts::exists(*k)));
+				assert_eq!(Accounts::iter_keys().count(), 7);
+
+				// corrupt entries are removed as well.
+				io_storage::set(Accounts::raw_storage_key(10), vec![]);
+
+				let rest = Accounts::drain().collect::<Vec<_>>();
+				assert_eq!(rest.len(), 8);
+				assert_eq!(rest.iter().filter(|entry| entry.is_err()).count(), 1);
+				assert_eq!(io_storage::iter_prefix(&Accounts::prefix()).count(), 0);
+			});
+		}
+
+		#[test]
+		fn map_translate_works() {
+			new_test_ext().execute_with(|| {
+				// values used to be `u16`s.
+				for k in 0..10u32 {
+					io_storage::set(Accounts::raw_storage_key(k), (k as u16).encode());
+				}
+				io_storage::set(Accounts::raw_storage_key(10), vec![1]);
+
+				let result =
+					Accounts::translate(|k, old: u16| (k % 2 == 0).then_some(old as u32 * 3));
+				assert!(matches!(
+					&result.unwrap_err()[..],
+					[StorageError::Corrupt { key, .. }] if *key == Accounts::raw_storage_key(10)
+				));
+				for k in 0..10 {
+					assert_eq!(Accounts::get(k), (k % 2 == 0).then_some(k * 3));
+				}
+				// the corrupt entry is left alone.
+				assert_eq!(
+					io_storage::get(Accounts::raw_storage_key(10)),
+					Some(vec![1])
+				);
+
+				// the defaults of a value query are removed, as with any other write.
+				for k in 0..4u32 {
+					io_storage::set(Nonces::raw_storage_key(k), (k as u16).encode());
+				}
+				Nonces::translate(|k, old: u16| Some(old as u32 * (k % 2))).unwrap();
+				let mut keys = Nonces::iter_keys().collect::<Result<Vec<_>, _>>().unwrap();
+				keys.sort();
+				assert_eq!(keys, vec![1, 3]);
+				assert_eq!(Nonces::get(3), 3);
+			});
+		}
+
+		#[test]
+		fn try_mutate_only_writes_on_success() {
+			new_test_ext().execute_with(|| {
+				Accounts::set(1, 10);
+				let result = Accounts::try_mutate(1, |value| {
+					*value = Some(20);
+					Err("nope")
+				});
+				assert_eq!(result, Err::<(), _>("nope"));
+				assert_eq!(Accounts::get(1), Some(10));
+
+				let doubled = Accounts::try_mutate(1, |value| {
+					let doubled = value.ok_or("missing")? * 2;
+					*value = Some(doubled);
+					Ok::<_, &str>(doubled)
+				});
+				assert_eq!(doubled, Ok(20));
+				assert_eq!(Accounts::get(1), Some(20));
+
+				// an error leaves a missing value missing as well.
+				assert!(Accounts::try_mutate_exists(2, |value| value.ok_or("missing")).is_err());
+				assert!(!Accounts::exists(2));
+				assert_eq!(Accounts::mutate_exists(2, |value| value.replace(5)), None);
+				assert_eq!(Accounts::get(2), Some(5));
+
+				AccountCount::set(3);
+				assert_eq!(
+					AccountCount::try_mutate(|_| Err::<(), _>("nope")),
+					Err("nope")
+				);
+				assert_eq!(AccountCount::mutate(|count| *count + 1), 4);
+				assert_eq!(AccountCount::get(), 3);
+				assert_eq!(AccountCount::take(), 3);
+				assert!(!AccountCount::exists());
+			});
+		}
+
+		#[test]
+		fn take_insert_and_swap() {
+			new_test_ext().execute_with(|| {
+				assert_eq!(Accounts::insert(1, 10), None);
+				assert_eq!(Accounts::insert(1, 11), Some(10));
+				assert_eq!(Accounts::take(1), Some(11));
+				assert_eq!(Accounts::take(1), None);
+				assert!(!Accounts::exists(1));
+
+				Accounts::set(1, 10);
+				Accounts::set(2, 20);
+				Accounts::swap(1, 2);
+				assert_eq!((Accounts::get(1), Accounts::get(2)), (Some(20), Some(10)));
+				// swapping with a missing value moves the value.
+				Accounts::swap(1, 3);
+				assert_eq!((Accounts::get(1), Accounts::get(3)), (None, Some(20)));
+			});
+		}
+
+		#[test]
+		fn mutating_corrupt_values_keeps_their_bytes() {
+			new_test_ext().execute_with(|| {
+				let key = Accounts::raw_storage_key(5);
+				io_storage::set(&key, vec![1]);
+
+				Accounts::mutate(5, |value| assert_eq!(*value, None));
+				assert_eq!(Accounts::take(5), None);
+				assert_eq!(Accounts::mutate_exists(5, |value| value.take()), None);
+				assert!(Accounts::try_mutate(5, |value| value.ok_or("missing")).is_err());
+				CountedAccounts::mutate(5, |value| *value = None);
+				assert_eq!(io_storage::get(&key), Some(vec![1]));
+				assert!(Accounts::try_get(5).is_err());
+
+				// setting a new value on purpose still replaces them.
+				Accounts::mutate(5, |value| *value = Some(50));
+				assert_eq!(Accounts::try_get(5), Ok(Some(50)));
+			});
+		}
+
+		#[test]
+		fn corrupt_values_are_reported_by_try_get() {
+			new_test_ext().execute_with(|| {
+				assert_eq!(Accounts::try_get(1), Ok(None));
+				Accounts::set(1, 10);
+				assert_eq!(Accounts::try_get(1), Ok(Some(10)));
+
+				// too short, and too long.
+				io_storage::set(Accounts::raw_storage_key(2), vec![1]);
+				io_storage::set(Accounts::raw_storage_key(3), 30u64.encode());
+				for k in [2, 3] {
+					assert_eq!(Accounts::get(k), None);
+					assert!(Accounts::exists(k));
+					let key = Accounts::raw_storage_key(k);
+					assert!(matches!(
+						Accounts::try_get(k),
+						Err(StorageError::Corrupt { key: corrupt_key, .. }) if corrupt_key == key
+					));
+				}
+
+				io_storage::set(AccountCount::raw_storage_key(), vec![1]);
+				assert_eq!(AccountCount::get(), 0);
+				assert!(AccountCount::exists());
+				assert!(AccountCount::try_get().is_err());
+			});
+		}
+
+		#[test]
+		fn append_and_decode_len() {
+			new_test_ext().execute_with(|| {
+				assert_eq!(Nominations::decode_len(1), None);
+				Nominations::append(1, 10);
+				Nominations::append(1, 11);
+				assert_eq!(Nominations::get(1), vec![10, 11]);
+				assert_eq!(Nominations::decode_len(1), Some(2));
+				assert_eq!(Nominations::get(2), vec![]);
+
+				Nominations::set(2, vec![20]);
+				Nominations::append(2, 21);
+				assert_eq!(Nominations::get(2), vec![20, 21]);
+				assert_eq!(Nominations::decode_len(2), Some(2));
+
+				// a corrupt value is not replaced.
+				io_storage::set(Nominations::raw_storage_key(3), vec![]);
+				Nominations::append(3, 30);
+				assert!(Nominations::try_get(3).is_err());
+				assert_eq!(
+					io_storage::get(Nominations::raw_storage_key(3)),
+					Some(vec![])
+				);
+			});
+		}
+
+		struct NoOwner;
+		impl Get<&'static str> for NoOwner {
+			fn get() -> &'static str {
+				"no owner"
+			}
+		}
+
+		#[test]
+		fn value_query_removes_defaults() {
+			new_test_ext().execute_with(|| {
+				assert_eq!(Nonces::get(1), 0);
+				Nonces::mutate(1, |nonce| *nonce += 1);
+				assert_eq!(Nonces::get(1), 1);
+				assert!(Nonces::exists(1));
+
+				// going back to the default removes the entry.
+				Nonces::mutate(1, |nonce| *nonce -= 1);
+				assert!(!Nonces::exists(1));
+				Nonces::set(2, 0);
+				assert!(!Nonces::exists(2));
+				assert_eq!(Nonces::iter_keys().count(), 0);
+
+				Nonces::set(2, 5);
+				assert_eq!(Nonces::take(2), 5);
+				assert_eq!(Nonces::take(2), 0);
+			});
+		}
+
+		#[test]
+		fn result_query_reports_missing_values() {
+			new_test_ext().execute_with(|| {
+				assert_eq!(Owner::get(), Err("no owner"));
+				Owner::set(AccountId(1));
+				assert_eq!(Owner::get(), Ok(AccountId(1)));
+
+				// an error removes the value.
+				Owner::mutate(|owner| *owner = Err("gone"));
+				assert!(!Owner::exists());
+				assert_eq!(Owner::get(), Err("no owner"));
+			});
+		}
+
+		struct CountedAccounts;
+		impl CountedStorageMap for CountedAccounts {
+			type Map = Accounts;
+			type Counter = AccountCount;
+		}
+
+		struct CountedNonces;
+		impl CountedStorageMap for CountedNonces {
+			type Map = Nonces;
+			type Counter = NonceCount;
+		}
+
+		#[test]
+		fn counted_map_skips_defaults_of_value_queries() {
+			new_test_ext().execute_with(|| {
+				CountedNonces::set(1, 0);
+				CountedNonces::set(1, 0);
+				CountedNonces::mutate(2, |nonce| *nonce = Some(0));
+				assert_eq!(CountedNonces::count(), 0);
+				assert_eq!(CountedNonces::check_count(), Ok(0));
+
+				CountedNonces::set(1, 5);
+				CountedNonces::mutate(2, |nonce| *nonce = Some(7));
+				assert_eq!(CountedNonces::check_count(), Ok(2));
+
+				// going back to the default removes the entry, and its count.
+				CountedNonces::set(1, 0);
+				CountedNonces::mutate(2, |nonce| *nonce = Some(0));
+				assert_eq!(CountedNonces::check_count(), Ok(0));
+			});
+		}
+
+		#[test]
+		fn counted_map_tracks_entries() {
+			new_test_ext().execute_with(|| {
+				assert_eq!(CountedAccounts::count(), 0);
+				(0..5).for_each(|k| CountedAccounts::set(k, k));
+				CountedAccounts::set(3, 30);
+				assert_eq!(CountedAccounts::count(), 5);
+
+				CountedAccounts::clear(3);
+				CountedAccounts::clear(3);
+				assert_eq!(CountedAccounts::count(), 4);
+				assert_eq!(CountedAccounts::get(3), None);
+
+				// created..
+				CountedAccounts::mutate(10, |v| *v = Some(100));
+				// ..changed..
+				CountedAccounts::mutate(10, |v| *v = v.map(|v| v + 1));
+				// ..left absent..
+				CountedAccounts::mutate(11, |v| assert!(v.is_none()));
+				assert_eq!(CountedAccounts::count(), 5);
+				// ..and removed.
+				CountedAccounts::mutate(0, |v| *v = None);
+				assert_eq!(CountedAccounts::count(), 4);
+				assert_eq!(CountedAccounts::check_count(), Ok(4));
+
+				// failed mutations are not counted.
+				let result = CountedAccounts::try_mutate(20, |v| {
+					*v = Some(1);
+					Err::<(), _>("nope")
+				});
+				assert!(result.is_err());
+				assert_eq!(CountedAccounts::take(10), Some(101));
+				assert_eq!(CountedAccounts::count(), 3);
+
+				(0..20).for_each(CountedAccounts::clear);
+				assert_eq!(CountedAccounts::count(), 0);
+				// an empty count is not stored.
+				assert!(!AccountCount::exists());
+			});
+		}
+
+		#[test]
+		fn counted_map_detects_bypassed_writes() {
+			new_test_ext().execute_with(|| {
+				CountedAccounts::set(1, 1);
+				Accounts::set(2, 2);
+				let mismatch = CountMismatch {
+					stored: 1,
+					actual: 2,
+				};
+				assert_eq!(CountedAccounts::check_count(), Err(mismatch));
+
+				Accounts::clear(1);
+				Accounts::clear(2);
+				let mismatch = CountMismatch {
+					stored: 1,
+					actual: 0,
+				};
+				assert_eq!(CountedAccounts::check_count(), Err(mismatch));
+			});
+		}
+
+		struct Allowances;
+		impl StorageDoubleMap for Allowances {
+			type Key1 = AccountId;
+			type Key2 = AccountId;
+			type Value = u32;
+			type Hasher1 = Blake2_128Concat;
+			type Hasher2 = Twox64Concat;
+			fn module_prefix() -> &'static str {
+				"Test"
+			}
+			fn storage_prefix() -> &'static str {
+				"Allowances"
+			}
+		}
 
-			// Notice how `MyRuntime as staking_module::Config` is an equivalent type to
-			// `currency_module::Module<MyRuntime>`.
+		#[test]
+		fn double_map_keys_are_hashed_separately() {
+			let (alice, bob) = (AccountId(1), AccountId(2));
 			assert_eq!(
-				<MyRuntime as staking_module::Config>::Currency::reserved_balance(alice),
-				Some(0)
+				Allowances::raw_storage_key(alice, bob),
+				[
+					Allowances::prefix(),
+					Blake2_128Concat::hash(&alice.encode()),
+					Twox64Concat::hash(&bob.encode()),
+				]
+				.concat()
 			);
-			assert_eq!(
-				currency_module::Module::<MyRuntime>::reserved_balance(alice),
-				Some(0)
+			assert!(
+				Allowances::raw_storage_key(alice, bob).starts_with(&Allowances::prefix_for(alice))
 			);
+		}
 
-			// Unlike in production code, an unwrap is perfectly fine here.
-			staking_module::Call::<MyRuntime>::Bond { amount }
-				.dispatch(alice)
-				.unwrap();
+		#[test]
+		fn double_map_works() {
+			new_test_ext().execute_with(|| {
+				let (alice, bob) = (AccountId(1), AccountId(2));
+				assert_eq!(Allowances::get(alice, bob), None);
+
+				Allowances::set(alice, bob, 10);
+				assert_eq!(Allowances::get(alice, bob), Some(10));
+				assert!(Allowances::contains(alice, bob));
+				// the order of the keys matters.
+				assert!(!Allowances::contains(bob, alice));
+
+				Allowances::mutate(alice, bob, |maybe_v| *maybe_v = maybe_v.map(|v| v + 5));
+				assert_eq!(Allowances::get(alice, bob), Some(15));
+				Allowances::mutate(alice, bob, |maybe_v| *maybe_v = None);
+				assert!(!Allowances::contains(alice, bob));
+
+				Allowances::set(alice, bob, 10);
+				Allowances::clear(alice, bob);
+				assert_eq!(Allowances::get(alice, bob), None);
+			});
+		}
 
-			assert_eq!(
-				<MyRuntime as staking_module::Config>::Currency::free_balance(alice),
-				Some(50)
-			);
-			assert_eq!(
-				<MyRuntime as staking_module::Config>::Currency::reserved_balance(alice),
-				Some(50)
+		#[test]
+		fn double_map_prefix_iteration() {
+			new_test_ext().execute_with(|| {
+				let owner = AccountId(100);
+				for spender in 0..10 {
+					Allowances::set(owner, AccountId(spender), spender * 10);
+					Allowances::set(AccountId(spender), owner, 1);
+				}
+				// something else right under the prefix of `owner`, that is not an entry.
+				io_storage::set(
+					[Allowances::prefix_for(owner), vec![1, 2]].concat(),
+					vec![0],
+				);
+
+				let (mut allowed, corrupt): (Vec<_>, Vec<_>) =
+					Allowances::iter_prefix(owner).partition(Result::is_ok);
+				allowed.sort_by_key(|entry| entry.as_ref().unwrap().0 .0);
+				let expected = (0..10)
+					.map(|s| Ok((AccountId(s), s * 10)))
+					.collect::<Vec<_>>();
+				assert_eq!(allowed, expected);
+				assert!(matches!(
+					corrupt[..],
+					[Err(StorageError::Corrupt { ref key, .. })] if key.ends_with(&[1, 2])
+				));
+
+				assert_eq!(Allowances::clear_prefix(owner), 11);
+				assert_eq!(Allowances::iter_prefix(owner).count(), 0);
+				let remaining = Allowances::iter_prefix(AccountId(5)).collect::<Vec<_>>();
+				assert_eq!(remaining, [Ok((owner, 1))]);
+			});
+		}
+
+		struct Approvals;
+		impl StorageNMap for Approvals {
+			type Key = (
+				NMapKey<Blake2_128Concat, u32>,
+				NMapKey<Blake2_128Concat, AccountId>,
+				NMapKey<Twox64Concat, AccountId>,
 			);
+			type Value = u32;
+			fn module_prefix() -> &'static str {
+				"Test"
+			}
+			fn storage_prefix() -> &'static str {
+				"Approvals"
+			}
+		}
+
+		#[test]
+		fn n_map_works() {
+			new_test_ext().execute_with(|| {
+				let (alice, bob) = (AccountId(1), AccountId(2));
+				let key = (7, alice, bob);
+				assert_eq!(
+					Approvals::raw_storage_key(key),
+					[
+						Approvals::prefix(),
+						Blake2_128Concat::hash(&7u32.encode()),
+						Blake2_128Concat::hash(&alice.encode()),
+						Twox64Concat::hash(&bob.encode()),
+					]
+					.concat()
+				);
+
+				assert_eq!(Approvals::get(key), None);
+				Approvals::set(key, 10);
+				assert_eq!(Approvals::get(key), Some(10));
+				assert!(!Approvals::contains((7, bob, alice)));
+
+				Approvals::mutate(key, |maybe_v| *maybe_v = maybe_v.map(|v| v * 2));
+				assert_eq!(Approvals::get(key), Some(20));
+				Approvals::mutate(key, |maybe_v| *maybe_v = None);
+				assert!(!Approvals::contains(key));
+
+				Approvals::set(key, 10);
+				Approvals::clear(key);
+				assert_eq!(Approvals::get(key), None);
+			});
+		}
+
+		#[test]
+		fn n_map_prefix_iteration() {
+			new_test_ext().execute_with(|| {
+				for asset in 0..3 {
+					for owner in 0..3 {
+						for delegate in 0..3 {
+							let value = asset * 100 + owner * 10 + delegate;
+							Approvals::set((asset, AccountId(owner), AccountId(delegate)), value);
+						}
+					}
+				}
+
+				let mut by_owner = Approvals::iter_prefix((1, AccountId(2)))
+					.collect::<Result<Vec<_>, _>>()
+					.unwrap();
+				by_owner.sort_by_key(|(_, value)| *value);
+				assert_eq!(
+					by_owner,
+					(0..3).map(|d| (AccountId(d), 120 + d)).collect::<Vec<_>>()
+				);
+
+				// the remaining parts are decoded as a tuple.
+				let mut by_asset = Approvals::iter_prefix((2,))
+					.collect::<Result<Vec<_>, _>>()
+					.unwrap();
+				by_asset.sort_by_key(|(_, value)| *value);
+				assert_eq!(by_asset.len(), 9);
+				assert_eq!(by_asset[5], ((AccountId(1), AccountId(2)), 212));
+
+				assert_eq!(Approvals::clear_prefix((2, AccountId(0))), 3);
+				assert_eq!(Approvals::iter_prefix((2,)).count(), 6);
+				assert_eq!(Approvals::clear_prefix((2,)), 6);
+				assert_eq!(Approvals::iter_prefix((1,)).count(), 9);
+			});
 		}
 	}
 
 	mod runtime_test {
 		use super::*;
+		use currency_module::{BalancesMap, TotalIssuance};
+
+		#[test]
+		fn storage_items_are_module_scoped() {
+			let registry = runtime::storage_registry();
+			assert_eq!(registry.items().len(), 2);
+			assert!(registry
+				.items()
+				.iter()
+				.all(|item| item.module == "MOD_CURRENCY"));
+			assert_eq!(registry.check(), Ok(()));
+		}
+
+		#[test]
+		fn corrupt_storage_is_found() {
+			setup().execute_with(|| {
+				assert_eq!(runtime::corrupt_storage(), vec![]);
+
+				let issuance_key = TotalIssuance::<MyRuntime>::raw_storage_key();
+				let balance_key = BalancesMap::<MyRuntime>::raw_storage_key(AccountId(7));
+				io_storage::set(&issuance_key, vec![1]);
+				io_storage::set(&balance_key, vec![1, 2, 3]);
+				// neither of them looks any different from a missing value to `get`.
+				assert_eq!(TotalIssuance::<MyRuntime>::get(), 0);
+				assert!(BalancesMap::<MyRuntime>::get(AccountId(7)).is_none());
+
+				let corrupt_keys = runtime::corrupt_storage()
+					.into_iter()
+					.map(|StorageError::Corrupt { key, .. }| key)
+					.collect::<Vec<_>>();
+				assert_eq!(corrupt_keys, vec![issuance_key, balance_key]);
+			});
+		}
 
 		#[test]
 		fn runtime_dispatch_works() {
-			setup();
-			let alice = AccountId(7);
-			let bob = AccountId(10);
+			setup().execute_with(|| {
+				let alice = AccountId(7);
+				let bob = AccountId(10);
 
-			let currency_call = currency_module::Call::<MyRuntime>::Transfer {
-				dest: bob,
-				amount: 10,
-			};
-			let outer_call = runtime::RuntimeCall::Currency(currency_call);
+				let currency_call = currency_module::Call::<MyRuntime>::Transfer {
+					dest: bob,
+					amount: 10,
+				};
+				let outer_call = runtime::RuntimeCall::Currency(currency_call);
+
+				outer_call.dispatch(alice).unwrap();
+
+				assert_eq!(
+					currency_module::BalancesMap::<MyRuntime>::get(alice)
+						.unwrap()
+						.free,
+					90
+				);
+				assert_eq!(
+					currency_module::BalancesMap::<MyRuntime>::get(bob)
+						.unwrap()
+						.free,
+					10
+				);
+			});
+		}
+
+		#[test]
+		fn runtime_dispatch_is_transactional() {
+			setup().execute_with(|| {
+				let alice = AccountId(7);
+				let bob = AccountId(10);
+
+				// a failing dispatch leaves no open transaction behind.
+				let failing_call =
+					runtime::RuntimeCall::Currency(currency_module::Call::Transfer {
+						dest: bob,
+						amount: 1000,
+					});
+				assert!(failing_call.dispatch(alice).is_err());
+				assert_eq!(io_storage::transaction_depth(), 0);
+
+				// a successful dispatch is reverted if its parent transaction is rolled back.
+				let result: DispatchResult = io_storage::with_transaction(|| {
+					runtime::RuntimeCall::Currency(currency_module::Call::Transfer {
+						dest: bob,
+						amount: 10,
+					})
+					.dispatch(alice)?;
+					assert!(currency_module::BalancesMap::<MyRuntime>::exists(bob));
+					Err(DispatchError::Other("revert everything"))
+				});
+				assert!(result.is_err());
+
+				assert_eq!(
+					currency_module::BalancesMap::<MyRuntime>::get(alice)
+						.unwrap()
+						.free,
+					100
+				);
+				assert!(!currency_module::BalancesMap::<MyRuntime>::exists(bob));
+			});
+		}
 
-			outer_call.dispatch(alice).unwrap();
+		#[test]
+		fn test_externalities_are_deterministic() {
+			let mut first = setup();
+			let mut second = setup();
+			first.execute_with(|| {
+				runtime::RuntimeCall::Currency(currency_module::Call::Transfer {
+					dest: AccountId(10),
+					amount: 10,
+				})
+				.dispatch(AccountId(7))
+				.unwrap();
+			});
 
+			// `second` is not affected by whatever happened in `first`.
 			assert_eq!(
-				currency_module::BalancesMap::<MyRuntime>::get(alice)
-					.unwrap()
-					.free,
-				90
+				second
+					.execute_with(|| currency_module::BalancesMap::<MyRuntime>::get(AccountId(7)))
+					.map(|b| b.free),
+				Some(100)
 			);
 			assert_eq!(
-				currency_module::BalancesMap::<MyRuntime>::get(bob)
-					.unwrap()
-					.free,
-				10
+				setup().execute_with(io_storage::storage_root),
+				second.execute_with(io_storage::storage_root)
 			);
 		}
 	}
@@ -0,0 +1,404 @@
+//! Collections with an upper bound on their number of items, to keep stored values from growing
+//! without limit.
+//!
+//! * [`BoundedVec`]: a `Vec` of at most `S::get()` items.
+//! * [`BoundedBTreeMap`]: a `BTreeMap` of at most `S::get()` entries.
+//!
+//! Both are encoded exactly like their unbounded counterpart, i.e. a compact length followed by
+//! the items, such that one can be turned into the other without any migration. Decoding,
+//! however, fails as soon as the length is found to exceed the bound, before any item is decoded.
+//!
+//! The bound is given by a [`Get`] type, such as [`ConstU32`], or any `Config` item of a module.
+//! The length of an encoded collection can be read without decoding it, see [`DecodeLength`].
+
+use crate::get::Get;
+use parity_scale_codec::{Compact, Decode, Encode, Error, Input, Output};
+use std::{collections::BTreeMap, fmt, marker::PhantomData, ops::Deref};
+
+/// A [`Get`] of the constant `N`.
+pub struct ConstU32<const N: u32>;
+
+impl<const N: u32> Get<u32> for ConstU32<N> {
+	fn get() -> u32 {
+		N
+	}
+}
+
+/// Something whose number of items can be read out of its encoding, without decoding the items.
+pub trait DecodeLength {
+	/// The number of items in `encoded`, which must be the encoding of `Self`.
+	fn decode_len(encoded: &[u8]) -> Result<usize, Error>;
+}
+
+/// Read the compact length that prefixes the encoding of all collections.
+fn decode_compact_len(mut encoded: &[u8]) -> Result<usize, Error> {
+	Ok(Compact::<u32>::decode(&mut encoded)?.0 as usize)
+}
+
+/// Decode the compact length that prefixes the encoding of a collection, failing if it is more
+/// than `S::get()`.
+fn decode_bounded_len<S: Get<u32>, I: Input>(input: &mut I) -> Result<u32, Error> {
+	let len = Compact::<u32>::decode(input)?.0;
+	if len > S::get() {
+		return Err("collection exceeds its bound".into());
+	}
+	Ok(len)
+}
+
+impl<T> DecodeLength for Vec<T> {
+	fn decode_len(encoded: &[u8]) -> Result<usize, Error> {
+		decode_compact_len(encoded)
+	}
+}
+
+impl<K, V> DecodeLength for BTreeMap<K, V> {
+	fn decode_len(encoded: &[u8]) -> Result<usize, Error> {
+		decode_compact_len(encoded)
+	}
+}
+
+/// A `Vec` of at most `S::get()` items.
+///
+/// It can be read as a `Vec`, but only changed through methods that respect the bound.
+///
+/// ```
+/// # use pba_pre_course_assignment::l_mini_substrate::bounded::{BoundedVec, ConstU32};
+/// let mut nominations = BoundedVec::<u32, ConstU32<2>>::new();
+/// assert_eq!(nominations.try_push(1), Ok(()));
+/// assert_eq!(nominations.try_push(2), Ok(()));
+/// assert_eq!(nominations.try_push(3), Err(3));
+/// assert_eq!(*nominations, vec![1, 2]);
+/// ```
+pub struct BoundedVec<T, S>(Vec<T>, PhantomData<S>);
+
+impl<T, S: Get<u32>> BoundedVec<T, S> {
+	/// An empty vector.
+	pub fn new() -> Self {
+		Self(Vec::new(), PhantomData)
+	}
+
+	/// The maximum number of items.
+	pub fn bound() -> usize {
+		S::get() as usize
+	}
+
+	/// Append `item`, or give it back if the vector is already full.
+	pub fn try_push(&mut self, item: T) -> Result<(), T> {
+		if self.0.len() >= Self::bound() {
+			return Err(item);
+		}
+		self.0.push(item);
+		Ok(())
+	}
+
+	/// Remove the last item, if any.
+	pub fn pop(&mut self) -> Option<T> {
+		self.0.pop()
+	}
+
+	/// Remove the item at `index`, shifting all the items after it. Panics if `index` is out of
+	/// bounds.
+	pub fn remove(&mut self, index: usize) -> T {
+		self.0.remove(index)
+	}
+
+	/// Only keep the items for which `f` returns `true`.
+	pub fn retain(&mut self, f: impl FnMut(&T) -> bool) {
+		self.0.retain(f)
+	}
+
+	/// The items, as a mutable slice.
+	pub fn as_mut_slice(&mut self) -> &mut [T] {
+		self.0.as_mut_slice()
+	}
+
+	/// The items, without the bound.
+	pub fn into_inner(self) -> Vec<T> {
+		self.0
+	}
+}
+
+impl<T, S: Get<u32>> TryFrom<Vec<T>> for BoundedVec<T, S> {
+	type Error = Vec<T>;
+
+	/// Bound `items`, or give them back if there are too many.
+	fn try_from(items: Vec<T>) -> Result<Self, Vec<T>> {
+		if items.len() > Self::bound() {
+			return Err(items);
+		}
+		Ok(Self(items, PhantomData))
+	}
+}
+
+impl<T, S> From<BoundedVec<T, S>> for Vec<T> {
+	fn from(bounded: BoundedVec<T, S>) -> Self {
+		bounded.0
+	}
+}
+
+impl<T, S> Deref for BoundedVec<T, S> {
+	type Target = Vec<T>;
+
+	fn deref(&self) -> &Vec<T> {
+		&self.0
+	}
+}
+
+impl<T, S: Get<u32>> Default for BoundedVec<T, S> {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+impl<T: Clone, S> Clone for BoundedVec<T, S> {
+	fn clone(&self) -> Self {
+		Self(self.0.clone(), PhantomData)
+	}
+}
+
+impl<T: fmt::Debug, S> fmt::Debug for BoundedVec<T, S> {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		self.0.fmt(f)
+	}
+}
+
+impl<T: PartialEq, S> PartialEq for BoundedVec<T, S> {
+	fn eq(&self, other: &Self) -> bool {
+		self.0 == other.0
+	}
+}
+
+impl<T: Eq, S> Eq for BoundedVec<T, S> {}
+
+impl<T: Encode, S> Encode for BoundedVec<T, S> {
+	fn size_hint(&self) -> usize {
+		self.0.size_hint()
+	}
+
+	fn encode_to<O: Output + ?Sized>(&self, dest: &mut O) {
+		self.0.encode_to(dest)
+	}
+}
+
+impl<T: Decode, S: Get<u32>> Decode for BoundedVec<T, S> {
+	fn decode<I: Input>(input: &mut I) -> Result<Self, Error> {
+		let len = decode_bounded_len::<S, _>(input)?;
+		let items = (0..len)
+			.map(|_| T::decode(input))
+			.collect::<Result<_, _>>()?;
+		Ok(Self(items, PhantomData))
+	}
+}
+
+impl<T, S> DecodeLength for BoundedVec<T, S> {
+	fn decode_len(encoded: &[u8]) -> Result<usize, Error> {
+		decode_compact_len(encoded)
+	}
+}
+
+/// A `BTreeMap` of at most `S::get()` entries.
+///
+/// It can be read as a `BTreeMap`, but only changed through methods that respect the bound.
+///
+/// ```
+/// # use pba_pre_course_assignment::l_mini_substrate::bounded::{BoundedBTreeMap, ConstU32};
+/// let mut schedules = BoundedBTreeMap::<u32, u64, ConstU32<1>>::new();
+/// assert_eq!(schedules.try_insert(1, 10), Ok(None));
+/// // replacing an entry is always possible.
+/// assert_eq!(schedules.try_insert(1, 20), Ok(Some(10)));
+/// assert_eq!(schedules.try_insert(2, 30), Err((2, 30)));
+/// assert_eq!(schedules.get(&1), Some(&20));
+/// ```
+pub struct BoundedBTreeMap<K, V, S>(BTreeMap<K, V>, PhantomData<S>);
+
+impl<K: Ord, V, S: Get<u32>> BoundedBTreeMap<K, V, S> {
+	/// An empty map.
+	pub fn new() -> Self {
+		Self(BTreeMap::new(), PhantomData)
+	}
+
+	/// The maximum number of entries.
+	pub fn bound() -> usize {
+		S::get() as usize
+	}
+
+	/// Associate `value` with `key`, returning the value that was previously associated with it,
+	/// if any. If `key` is new and the map is already full, both are given back instead.
+	pub fn try_insert(&mut self, key: K, value: V) -> Result<Option<V>, (K, V)> {
+		if self.0.len() >= Self::bound() && !self.0.contains_key(&key) {
+			return Err((key, value));
+		}
+		Ok(self.0.insert(key, value))
+	}
+
+	/// Remove the entry of `key`, returning its value, if any.
+	pub fn remove(&mut self, key: &K) -> Option<V> {
+		self.0.remove(key)
+	}
+
+	/// The value associated with `key`, mutably.
+	pub fn get_mut(&mut self, key: &K) -> Option<&mut V> {
+		self.0.get_mut(key)
+	}
+
+	/// Only keep the entries for which `f` returns `true`.
+	pub fn retain(&mut self, f: impl FnMut(&K, &mut V) -> bool) {
+		self.0.retain(f)
+	}
+
+	/// The entries, without the bound.
+	pub fn into_inner(self) -> BTreeMap<K, V> {
+		self.0
+	}
+}
+
+impl<K: Ord, V, S: Get<u32>> TryFrom<BTreeMap<K, V>> for BoundedBTreeMap<K, V, S> {
+	type Error = BTreeMap<K, V>;
+
+	/// Bound `entries`, or give them back if there are too many.
+	fn try_from(entries: BTreeMap<K, V>) -> Result<Self, BTreeMap<K, V>> {
+		if entries.len() > Self::bound() {
+			return Err(entries);
+		}
+		Ok(Self(entries, PhantomData))
+	}
+}
+
+impl<K, V, S> From<BoundedBTreeMap<K, V, S>> for BTreeMap<K, V> {
+	fn from(bounded: BoundedBTreeMap<K, V, S>) -> Self {
+		bounded.0
+	}
+}
+
+impl<K, V, S> Deref for BoundedBTreeMap<K, V, S> {
+	type Target = BTreeMap<K, V>;
+
+	fn deref(&self) -> &BTreeMap<K, V> {
+		&self.0
+	}
+}
+
+impl<K: Ord, V, S: Get<u32>> Default for BoundedBTreeMap<K, V, S> {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+impl<K: Clone, V: Clone, S> Clone for BoundedBTreeMap<K, V, S> {
+	fn clone(&self) -> Self {
+		Self(self.0.clone(), PhantomData)
+	}
+}
+
+impl<K: fmt::Debug, V: fmt::Debug, S> fmt::Debug for BoundedBTreeMap<K, V, S> {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		self.0.fmt(f)
+	}
+}
+
+impl<K: PartialEq, V: PartialEq, S> PartialEq for BoundedBTreeMap<K, V, S> {
+	fn eq(&self, other: &Self) -> bool {
+		self.0 == other.0
+	}
+}
+
+impl<K: Eq, V: Eq, S> Eq for BoundedBTreeMap<K, V, S> {}
+
+impl<K: Encode, V: Encode, S> Encode for BoundedBTreeMap<K, V, S> {
+	fn size_hint(&self) -> usize {
+		self.0.size_hint()
+	}
+
+	fn encode_to<O: Output + ?Sized>(&self, dest: &mut O) {
+		self.0.encode_to(dest)
+	}
+}
+
+impl<K: Ord + Decode, V: Decode, S: Get<u32>> Decode for BoundedBTreeMap<K, V, S> {
+	fn decode<I: Input>(input: &mut I) -> Result<Self, Error> {
+		let len = decode_bounded_len::<S, _>(input)?;
+		let entries = (0..len)
+			.map(|_| <(K, V)>::decode(input))
+			.collect::<Result<_, _>>()?;
+		Ok(Self(entries, PhantomData))
+	}
+}
+
+impl<K, V, S> DecodeLength for BoundedBTreeMap<K, V, S> {
+	fn decode_len(encoded: &[u8]) -> Result<usize, Error> {
+		decode_compact_len(encoded)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use parity_scale_codec::DecodeAll;
+
+	type Nominations = BoundedVec<u32, ConstU32<3>>;
+	type Schedules = BoundedBTreeMap<u32, u64, ConstU32<2>>;
+
+	#[test]
+	fn encoded_like_unbounded_collections() {
+		let nominations = Nominations::try_from(vec![1, 2, 3]).unwrap();
+		assert_eq!(nominations.encode(), vec![1u32, 2, 3].encode());
+		assert_eq!(
+			Nominations::decode_all(&mut &*nominations.encode()),
+			Ok(nominations)
+		);
+
+		let entries = BTreeMap::from([(1, 10), (2, 20)]);
+		let schedules = Schedules::try_from(entries.clone()).unwrap();
+		assert_eq!(schedules.encode(), entries.encode());
+		assert_eq!(
+			Schedules::decode_all(&mut &*schedules.encode()),
+			Ok(schedules)
+		);
+	}
+
+	#[test]
+	fn oversized_input_is_rejected() {
+		assert_eq!(
+			Nominations::try_from(vec![1, 2, 3, 4]),
+			Err(vec![1, 2, 3, 4])
+		);
+		assert!(Nominations::decode(&mut &*vec![1u32, 2, 3, 4].encode()).is_err());
+		// the length alone is enough to fail, whatever follows it.
+		assert!(Nominations::decode(&mut &*Compact(u32::MAX).encode()).is_err());
+
+		let entries = BTreeMap::from([(1u32, 10u64), (2, 20), (3, 30)]);
+		assert_eq!(Schedules::try_from(entries.clone()), Err(entries.clone()));
+		assert!(Schedules::decode(&mut &*entries.encode()).is_err());
+	}
+
+	#[test]
+	fn bounds_are_kept_by_mutations() {
+		let mut nominations = Nominations::default();
+		(1..=3).for_each(|n| nominations.try_push(n).unwrap());
+		assert_eq!(nominations.try_push(4), Err(4));
+		nominations.retain(|n| *n != 2);
+		assert_eq!(nominations.try_push(4), Ok(()));
+		assert_eq!(nominations.into_inner(), vec![1, 3, 4]);
+
+		let mut schedules = Schedules::default();
+		assert_eq!(schedules.try_insert(1, 10), Ok(None));
+		assert_eq!(schedules.try_insert(2, 20), Ok(None));
+		assert_eq!(schedules.try_insert(3, 30), Err((3, 30)));
+		assert_eq!(schedules.remove(&1), Some(10));
+		assert_eq!(schedules.try_insert(3, 30), Ok(None));
+		assert_eq!(schedules.len(), 2);
+	}
+
+	#[test]
+	fn length_is_decoded_alone() {
+		let nominations = Nominations::try_from(vec![1, 2]).unwrap();
+		assert_eq!(Nominations::decode_len(&nominations.encode()), Ok(2));
+		assert_eq!(Vec::<u32>::decode_len(&nominations.encode()), Ok(2));
+		// the items are not looked at, let alone decoded.
+		assert_eq!(Nominations::decode_len(&[8]), Ok(2));
+		assert!(Nominations::decode_len(&[]).is_err());
+
+		let schedules = Schedules::try_from(BTreeMap::from([(1, 10)])).unwrap();
+		assert_eq!(Schedules::decode_len(&schedules.encode()), Ok(1));
+	}
+}
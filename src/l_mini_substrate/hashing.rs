@@ -0,0 +1,370 @@
+//! Hashing functions, implemented locally.
+//!
+//! * [`blake2_256`] and [`blake2_128`]: the BLAKE2b cryptographic hash (RFC 7693), with a 32 and 16
+//!   byte output respectively.
+//! * [`twox_64`] and [`twox_128`]: the xxHash64 non-cryptographic hash, which is much faster, but
+//!   must only be used on data that cannot be chosen by an attacker. The 128 bit variant is two
+//!   xxHash64 with seeds 0 and 1, concatenated.
+//!
+//! On top of these, the [`StorageHasher`]s define how the keys of a storage map are hashed.
+
+/// A 32 byte hash, as returned by [`blake2_256`].
+pub type Hash = [u8; 32];
+
+/// BLAKE2b initialization vector.
+const IV: [u64; 8] = [
+	0x6a09e667f3bcc908,
+	0xbb67ae8584caa73b,
+	0x3c6ef372fe94f82b,
+	0xa54ff53a5f1d36f1,
+	0x510e527fade682d1,
+	0x9b05688c2b3e6c1f,
+	0x1f83d9abfb41bd6b,
+	0x5be0cd19137e2179,
+];
+
+/// BLAKE2b message schedule, one permutation per round.
+const SIGMA: [[usize; 16]; 12] = [
+	[0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15],
+	[14, 10, 4, 8, 9, 15, 13, 6, 1, 12, 0, 2, 11, 7, 5, 3],
+	[11, 8, 12, 0, 5, 2, 15, 13, 10, 14, 3, 6, 7, 1, 9, 4],
+	[7, 9, 3, 1, 13, 12, 11, 14, 2, 6, 5, 10, 4, 0, 15, 8],
+	[9, 0, 5, 7, 2, 4, 10, 15, 14, 1, 11, 12, 6, 8, 3, 13],
+	[2, 12, 6, 10, 0, 11, 8, 3, 4, 13, 7, 5, 15, 14, 1, 9],
+	[12, 5, 1, 15, 14, 13, 4, 10, 0, 7, 6, 3, 9, 2, 8, 11],
+	[13, 11, 7, 14, 12, 1, 3, 9, 5, 0, 15, 4, 8, 6, 2, 10],
+	[6, 15, 14, 9, 11, 3, 0, 8, 12, 2, 13, 7, 1, 4, 10, 5],
+	[10, 2, 8, 4, 7, 6, 1, 5, 15, 11, 9, 14, 3, 12, 13, 0],
+	[0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15],
+	[14, 10, 4, 8, 9, 15, 13, 6, 1, 12, 0, 2, 11, 7, 5, 3],
+];
+
+/// The size of a single BLAKE2b block, in bytes.
+const BLOCK_LEN: usize = 128;
+
+/// The BLAKE2b mixing function.
+fn mix(v: &mut [u64; 16], a: usize, b: usize, c: usize, d: usize, x: u64, y: u64) {
+	v[a] = v[a].wrapping_add(v[b]).wrapping_add(x);
+	v[d] = (v[d] ^ v[a]).rotate_right(32);
+	v[c] = v[c].wrapping_add(v[d]);
+	v[b] = (v[b] ^ v[c]).rotate_right(24);
+	v[a] = v[a].wrapping_add(v[b]).wrapping_add(y);
+	v[d] = (v[d] ^ v[a]).rotate_right(16);
+	v[c] = v[c].wrapping_add(v[d]);
+	v[b] = (v[b] ^ v[c]).rotate_right(63);
+}
+
+/// Compress a single `block` into the state `h`, having processed `counter` bytes so far.
+fn compress(h: &mut [u64; 8], block: &[u8; BLOCK_LEN], counter: u128, last: bool) {
+	let mut m = [0u64; 16];
+	for (word, chunk) in m.iter_mut().zip(block.chunks_exact(8)) {
+		*word = u64::from_le_bytes(chunk.try_into().expect("chunks are 8 bytes; qed"));
+	}
+
+	let mut v = [0u64; 16];
+	v[..8].copy_from_slice(h);
+	v[8..].copy_from_slice(&IV);
+	v[12] ^= counter as u64;
+	v[13] ^= (counter >> 64) as u64;
+	if last {
+		v[14] = !v[14];
+	}
+
+	for s in SIGMA.iter() {
+		mix(&mut v, 0, 4, 8, 12, m[s[0]], m[s[1]]);
+		mix(&mut v, 1, 5, 9, 13, m[s[2]], m[s[3]]);
+		mix(&mut v, 2, 6, 10, 14, m[s[4]], m[s[5]]);
+		mix(&mut v, 3, 7, 11, 15, m[s[6]], m[s[7]]);
+		mix(&mut v, 0, 5, 10, 15, m[s[8]], m[s[9]]);
+		mix(&mut v, 1, 6, 11, 12, m[s[10]], m[s[11]]);
+		mix(&mut v, 2, 7, 8, 13, m[s[12]], m[s[13]]);
+		mix(&mut v, 3, 4, 9, 14, m[s[14]], m[s[15]]);
+	}
+
+	for i in 0..8 {
+		h[i] ^= v[i] ^ v[i + 8];
+	}
+}
+
+/// Unkeyed BLAKE2b of `data`, with an output of `OUT` bytes. `OUT` must be within `1..=64`.
+fn blake2b<const OUT: usize>(data: &[u8]) -> [u8; OUT] {
+	assert!(
+		OUT > 0 && OUT <= 64,
+		"BLAKE2b output must be between 1 and 64 bytes"
+	);
+
+	let mut h = IV;
+	h[0] ^= 0x0101_0000 ^ OUT as u64;
+
+	// all blocks but the last one. The last one is always processed separately, even if full.
+	let full_blocks = data.len().saturating_sub(1) / BLOCK_LEN;
+	for (i, chunk) in data.chunks_exact(BLOCK_LEN).take(full_blocks).enumerate() {
+		let block = chunk.try_into().expect("chunks are BLOCK_LEN bytes; qed");
+		compress(&mut h, block, ((i + 1) * BLOCK_LEN) as u128, false);
+	}
+
+	let rest = &data[full_blocks * BLOCK_LEN..];
+	let mut last = [0u8; BLOCK_LEN];
+	last[..rest.len()].copy_from_slice(rest);
+	compress(&mut h, &last, data.len() as u128, true);
+
+	let mut out = [0u8; OUT];
+	for (chunk, word) in out.chunks_mut(8).zip(h.iter()) {
+		chunk.copy_from_slice(&word.to_le_bytes()[..chunk.len()]);
+	}
+	out
+}
+
+/// BLAKE2b hash of `data`, with a 32 byte output.
+pub fn blake2_256(data: &[u8]) -> [u8; 32] {
+	blake2b::<32>(data)
+}
+
+/// BLAKE2b hash of `data`, with a 16 byte output.
+pub fn blake2_128(data: &[u8]) -> [u8; 16] {
+	blake2b::<16>(data)
+}
+
+/// xxHash64 primes.
+const PRIME64_1: u64 = 0x9e3779b185ebca87;
+const PRIME64_2: u64 = 0xc2b2ae3d27d4eb4f;
+const PRIME64_3: u64 = 0x165667b19e3779f9;
+const PRIME64_4: u64 = 0x85ebca77c2b2ae63;
+const PRIME64_5: u64 = 0x27d4eb2f165667c5;
+
+/// Read a little endian `u64` from the start of `data`.
+fn read_u64(data: &[u8]) -> u64 {
+	u64::from_le_bytes(data[..8].try_into().expect("sliced to 8 bytes; qed"))
+}
+
+/// Mix `input` into the accumulator `acc`.
+fn xxh64_round(acc: u64, input: u64) -> u64 {
+	acc.wrapping_add(input.wrapping_mul(PRIME64_2))
+		.rotate_left(31)
+		.wrapping_mul(PRIME64_1)
+}
+
+/// Merge the accumulator `value` into the hash `acc`.
+fn xxh64_merge(acc: u64, value: u64) -> u64 {
+	(acc ^ xxh64_round(0, value))
+		.wrapping_mul(PRIME64_1)
+		.wrapping_add(PRIME64_4)
+}
+
+/// The xxHash64 of `data`, with the given `seed`.
+fn xxh64(data: &[u8], seed: u64) -> u64 {
+	let mut stripes = data.chunks_exact(32);
+	let mut h = if data.len() >= 32 {
+		let mut v = [
+			seed.wrapping_add(PRIME64_1).wrapping_add(PRIME64_2),
+			seed.wrapping_add(PRIME64_2),
+			seed,
+			seed.wrapping_sub(PRIME64_1),
+		];
+		for stripe in stripes.by_ref() {
+			for (i, acc) in v.iter_mut().enumerate() {
+				*acc = xxh64_round(*acc, read_u64(&stripe[i * 8..]));
+			}
+		}
+		let h = v[0]
+			.rotate_left(1)
+			.wrapping_add(v[1].rotate_left(7))
+			.wrapping_add(v[2].rotate_left(12))
+			.wrapping_add(v[3].rotate_left(18));
+		v.iter().fold(h, |h, acc| xxh64_merge(h, *acc))
+	} else {
+		seed.wrapping_add(PRIME64_5)
+	};
+	h = h.wrapping_add(data.len() as u64);
+
+	let mut rest = stripes.remainder();
+	while rest.len() >= 8 {
+		h ^= xxh64_round(0, read_u64(rest));
+		h = h
+			.rotate_left(27)
+			.wrapping_mul(PRIME64_1)
+			.wrapping_add(PRIME64_4);
+		rest = &rest[8..];
+	}
+	if rest.len() >= 4 {
+		let word = u32::from_le_bytes(rest[..4].try_into().expect("sliced to 4 bytes; qed"));
+		h ^= (word as u64).wrapping_mul(PRIME64_1);
+		h = h
+			.rotate_left(23)
+			.wrapping_mul(PRIME64_2)
+			.wrapping_add(PRIME64_3);
+		rest = &rest[4..];
+	}
+	for byte in rest {
+		h ^= (*byte as u64).wrapping_mul(PRIME64_5);
+		h = h.rotate_left(11).wrapping_mul(PRIME64_1);
+	}
+
+	h ^= h >> 33;
+	h = h.wrapping_mul(PRIME64_2);
+	h ^= h >> 29;
+	h = h.wrapping_mul(PRIME64_3);
+	h ^ (h >> 32)
+}
+
+/// xxHash64 of `data`, as 8 little endian bytes.
+pub fn twox_64(data: &[u8]) -> [u8; 8] {
+	xxh64(data, 0).to_le_bytes()
+}
+
+/// Two xxHash64 of `data`, with seeds 0 and 1, as 16 little endian bytes.
+pub fn twox_128(data: &[u8]) -> [u8; 16] {
+	let mut out = [0u8; 16];
+	out[..8].copy_from_slice(&xxh64(data, 0).to_le_bytes());
+	out[8..].copy_from_slice(&xxh64(data, 1).to_le_bytes());
+	out
+}
+
+/// A way of hashing the keys of a storage map.
+pub trait StorageHasher {
+	/// The output of the hasher.
+	type Output: AsRef<[u8]>;
+
+	/// Hash the encoded key `x`.
+	fn hash(x: &[u8]) -> Self::Output;
+}
+
+/// A [`StorageHasher`] whose output still contains the original key, which can be recovered.
+pub trait ReversibleStorageHasher: StorageHasher {
+	/// Given the output of [`StorageHasher::hash`], or anything that starts with it, return what
+	/// follows the hash itself.
+	///
+	/// Returns `None` if `hashed` is too short to even contain the hash.
+	fn reverse(hashed: &[u8]) -> Option<&[u8]>;
+}
+
+/// Keeps the key as is. Only fit for keys that cannot be chosen by an attacker, and when iteration
+/// in the order of keys is desired.
+pub struct Identity;
+
+impl StorageHasher for Identity {
+	type Output = Vec<u8>;
+
+	fn hash(x: &[u8]) -> Vec<u8> {
+		x.to_vec()
+	}
+}
+
+impl ReversibleStorageHasher for Identity {
+	fn reverse(hashed: &[u8]) -> Option<&[u8]> {
+		Some(hashed)
+	}
+}
+
+/// `twox_64(key) ++ key`. Fast, but only fit for keys that cannot be chosen by an attacker.
+pub struct Twox64Concat;
+
+impl StorageHasher for Twox64Concat {
+	type Output = Vec<u8>;
+
+	fn hash(x: &[u8]) -> Vec<u8> {
+		[twox_64(x).as_ref(), x].concat()
+	}
+}
+
+impl ReversibleStorageHasher for Twox64Concat {
+	fn reverse(hashed: &[u8]) -> Option<&[u8]> {
+		hashed.get(8..)
+	}
+}
+
+/// `blake2_128(key) ++ key`. Fit for any key, including ones chosen by an attacker.
+pub struct Blake2_128Concat;
+
+impl StorageHasher for Blake2_128Concat {
+	type Output = Vec<u8>;
+
+	fn hash(x: &[u8]) -> Vec<u8> {
+		[blake2_128(x).as_ref(), x].concat()
+	}
+}
+
+impl ReversibleStorageHasher for Blake2_128Concat {
+	fn reverse(hashed: &[u8]) -> Option<&[u8]> {
+		hashed.get(16..)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn hex(bytes: &[u8]) -> String {
+		bytes.iter().map(|b| format!("{:02x}", b)).collect()
+	}
+
+	#[test]
+	fn blake2b_test_vectors() {
+		assert_eq!(
+			hex(&blake2b::<64>(b"abc")),
+			"ba80a53f981c4d0d6a2797b69f12f6e94c212f14685ac4b74b12bb6fdbffa2d1\
+			 7d87c5392aab792dc252d5de4533cc9518d38aa8dbf1925ab92386edd4009923"
+		);
+		assert_eq!(
+			hex(&blake2_256(b"")),
+			"0e5751c026e543b2e8ab2eb06099daa1d1e5df47778f7787faab45cdf12fe3a8"
+		);
+		assert_eq!(hex(&blake2_128(b"")), "cae66941d9efbd404e4d88758ea67670");
+	}
+
+	#[test]
+	fn blake2b_multiple_blocks() {
+		// exactly one block, and a bit more than two blocks.
+		assert_eq!(
+			hex(&blake2_256(&[0u8; 128])),
+			"378d0caaaa3855f1b38693c1d6ef004fd118691c95c959d4efa950d6d6fcf7c1"
+		);
+		assert_eq!(
+			hex(&blake2_256(&(0..=255u8).collect::<Vec<_>>())),
+			"39a7eb9fedc19aabc83425c6755dd90e6f9d0c804964a1f4aaeea3b9fb599835"
+		);
+	}
+
+	#[test]
+	fn twox_test_vectors() {
+		assert_eq!(xxh64(b"", 0), 0xef46db3751d8e999);
+		assert_eq!(xxh64(b"a", 0), 0xd24ec4f1a98c6e5b);
+		assert_eq!(xxh64(b"abc", 0), 0x44bc2cf5ad770999);
+		// more than one stripe of 32 bytes.
+		assert_eq!(
+			xxh64(b"Nobody inspects the spammish repetition", 0),
+			0xfbcea83c8a378bf1
+		);
+		// well known storage prefixes.
+		assert_eq!(
+			hex(&twox_128(b"System")),
+			"26aa394eea5630e07c48ae0c9558cef7"
+		);
+		assert_eq!(
+			hex(&twox_128(b"Balances")),
+			"c2261276cc9d1f8598ea4b6a74b15c2f"
+		);
+		assert_eq!(
+			hex(&twox_128(b"TotalIssuance")),
+			"57c875e4cff74148e4628f264b974c80"
+		);
+	}
+
+	#[test]
+	fn concat_hashers_are_reversible() {
+		let key = [1u8, 2, 3];
+		assert_eq!(Identity::reverse(&Identity::hash(&key)), Some(&key[..]));
+		assert_eq!(
+			Twox64Concat::reverse(&Twox64Concat::hash(&key)),
+			Some(&key[..])
+		);
+		assert_eq!(
+			Blake2_128Concat::reverse(&Blake2_128Concat::hash(&key)),
+			Some(&key[..])
+		);
+
+		assert_eq!(Twox64Concat::hash(&key)[..8], twox_64(&key));
+		assert_eq!(Blake2_128Concat::hash(&key)[..16], blake2_128(&key));
+		assert_eq!(Blake2_128Concat::reverse(&[0; 10]), None);
+	}
+}
@@ -0,0 +1,988 @@
+//! Everything to do with your storage. This is the backing storage that will support all your
+//! module and eventually the runtime. It works off a key-value basis. You ask it for value stored
+//! under some key, or store a new value under some key.
+//!
+//! Both keys and values are opaque bytes (`Vec<u8>`). Keys can be passed by reference, as anything
+//! that is `AsRef<[u8]>`, and values can be looked at in place, without being copied out of the
+//! storage, with [`read_with`].
+//!
+//! In a real blockchain, this would be a database. By default, we use a simple thread-local map to
+//! store all the data.
+//!
+//! ### Transactions
+//!
+//! On top of the backend, the storage keeps a stack of *overlay* layers. [`start_transaction`]
+//! pushes a new (empty) layer, and while any layer is open, all writes land in the top-most one
+//! instead of the backend. Reads look through the layers from top to bottom, before falling back to
+//! the backend.
+//!
+//! [`commit_transaction`] merges the top-most layer into the one below it (or into the backend, if
+//! it was the last one), while [`rollback_transaction`] simply throws it away, reverting every
+//! write made since the matching [`start_transaction`]. Transactions can be nested arbitrarily
+//! deep.
+//!
+//! Most of the time, you want to use [`with_transaction`] rather than the raw functions.
+//!
+//! ### Iteration
+//!
+//! Keys are kept in lexicographic order, which allows walking over them with [`next_key`] and
+//! [`iter_prefix`], and removing whole ranges of them with [`clear_prefix`]. All of these see
+//! through the open transactions, exactly like [`get`] does.
+//!
+//! ### Backends
+//!
+//! The committed data lives in a [`StorageBackend`]. By default this is an [`InMemoryBackend`],
+//! but any other backend can be installed for the current thread with [`set_backend`]. Everything
+//! built on top of this module, such as `StorageValue` and `StorageMap`, is oblivious to which
+//! backend is in use.
+//!
+//! For data that should outlive the process, there is the [`FileBackend`]. Note that a persistent
+//! backend only keeps what has been [`commit`]ed.
+//!
+//! Point reads of the backend go through an LRU cache, see [`set_cache_capacity`] and
+//! [`cache_stats`].
+//!
+//! For data that should be visible on several threads, there is the [`SharedStorage`], which
+//! allows many concurrent readers, one writer at a time, and consistent snapshots.
+//!
+//! ### State root
+//!
+//! A Merkle trie is kept over the whole key space, such that [`storage_root`] commits to all the
+//! data in storage with a single hash.
+//!
+//! Reads of any set of keys can be proven against such a root with [`prove_read`], and the proof
+//! checked by anyone who trusts the root, without holding the storage, with [`verify_read`].
+//!
+//! ### Snapshots
+//!
+//! The whole state can be exported with [`export_state`] and loaded back with [`import_state`],
+//! and two such snapshots can be compared with [`diff`].
+//!
+//! ### Testing
+//!
+//! Everything above operates on the storage of the current thread. In tests, a
+//! [`TestExternalities`] provides an isolated storage instead.
+//!
+//! ### Metering
+//!
+//! All reads and writes are counted, per dispatch and per block. See [`StorageStats`].
+//!
+//! ### History
+//!
+//! The storage also keeps track of the current [`block_number`]. Once a block is finalized with
+//! [`finalize_block`], the value any key had at the end of it can be queried with [`get_at`], as
+//! long as it is within the pruning window set by [`set_pruning_window`].
+
+mod backend;
+mod cache;
+mod externalities;
+mod file;
+mod history;
+mod proof;
+mod shared;
+mod snapshot;
+mod stats;
+mod trie;
+
+pub use backend::{InMemoryBackend, StorageBackend};
+pub use cache::{
+	cache_stats, reset_cache_stats, set_cache_capacity, CacheStats, DEFAULT_CACHE_CAPACITY,
+};
+pub use externalities::TestExternalities;
+pub use file::FileBackend;
+pub use history::{block_number, finalize_block, get_at, set_pruning_window, HistoryError};
+pub use proof::{prove_read, verify_read, ProofError, StorageProof};
+pub use shared::{SharedSnapshot, SharedStorage};
+pub use snapshot::{diff, export_state, import_state, StateDiff, StateSnapshot};
+pub use stats::{
+	block_stats, dispatch_stats, reset_block_stats, reset_dispatch_stats, StorageStats,
+};
+
+use crate::l_mini_substrate::hashing::{blake2_256, Hash};
+use parity_scale_codec::{Compact, CompactLen, Decode, Encode};
+use std::{
+	cell::RefCell,
+	collections::{BTreeMap, BTreeSet},
+	ops::Bound::{self, Excluded, Included, Unbounded},
+};
+
+use backend::bound_as_ref;
+use cache::ReadCache;
+use history::History;
+use stats::Meter;
+use trie::Trie;
+
+pub type Key = Vec<u8>;
+pub type Value = Vec<u8>;
+
+/// A single transactional layer. `None` marks a key that has been cleared in this layer.
+type Overlay = BTreeMap<Key, Option<Value>>;
+
+/// Everything that this module keeps track of.
+struct State {
+	/// Where the data lives once it has left all transactions.
+	backend: Box<dyn StorageBackend>,
+	/// The most recently read values of the backend.
+	cache: ReadCache,
+	/// The stack of open transactions, the last one being the top-most.
+	transactions: Vec<Overlay>,
+	/// The Merkle trie over all the data, as of the last [`storage_root`].
+	trie: Trie,
+	/// The keys that have been written since the last [`storage_root`].
+	trie_dirty: BTreeSet<Key>,
+	/// Whether the trie must be rebuilt from scratch, e.g. because the backend was replaced.
+	trie_stale: bool,
+	/// The counters of all storage accesses.
+	meter: Meter,
+	/// The previous values of all keys, per block.
+	history: History,
+}
+
+impl Default for State {
+	fn default() -> Self {
+		Self {
+			backend: Box::<InMemoryBackend>::default(),
+			cache: ReadCache::default(),
+			transactions: Vec::new(),
+			trie: Trie::default(),
+			trie_dirty: BTreeSet::new(),
+			trie_stale: false,
+			meter: Meter::default(),
+			history: History::default(),
+		}
+	}
+}
+
+impl State {
+	/// Read the value under `key`, looking through all layers from top to bottom.
+	fn read(&self, key: &[u8]) -> Option<Value> {
+		match self
+			.transactions
+			.iter()
+			.rev()
+			.find_map(|overlay| overlay.get(key))
+		{
+			Some(maybe_value) => maybe_value.clone(),
+			None => self.backend.get(key),
+		}
+	}
+
+	/// Call `f` with the value under `key`, without copying it.
+	///
+	/// Like [`Self::read`], but going through the cache when falling back to the backend. The read
+	/// is metered.
+	fn read_with<R>(&mut self, key: &[u8], f: impl FnOnce(Option<&[u8]>) -> R) -> R {
+		self.check_external_writes();
+		let meter = &mut self.meter;
+		let f = |maybe_value: Option<&[u8]>| {
+			meter.read(maybe_value.map_or(0, <[u8]>::len));
+			f(maybe_value)
+		};
+
+		let in_overlay = self
+			.transactions
+			.iter()
+			.rev()
+			.find_map(|overlay| overlay.get(key));
+		match in_overlay {
+			Some(maybe_value) => f(maybe_value.as_deref()),
+			None => self.cache.read_with(key, &*self.backend, f),
+		}
+	}
+
+	/// Throw away everything derived from the backend if it has been written to from elsewhere.
+	fn check_external_writes(&mut self) {
+		if self.backend.has_external_writes() {
+			self.trie_stale = true;
+			self.cache.clear();
+		}
+	}
+
+	/// The first key in `(from, ..)` that currently has a value, in any layer.
+	fn next_key(&self, from: Bound<&[u8]>) -> Option<Key> {
+		let mut from = match from {
+			Included(key) => Included(key.to_vec()),
+			Excluded(key) => Excluded(key.to_vec()),
+			Unbounded => Unbounded,
+		};
+		loop {
+			let range = (from.as_ref(), Unbounded);
+			// the smallest key mentioned by any layer, be it a value or a removal.
+			let candidate = self
+				.transactions
+				.iter()
+				.filter_map(|overlay| {
+					overlay
+						.range::<Key, _>(range)
+						.next()
+						.map(|(k, _)| k.clone())
+				})
+				.chain(self.backend.next_key(bound_as_ref(&from)))
+				.min()?;
+
+			if self.read(&candidate).is_some() {
+				return Some(candidate);
+			}
+			from = Excluded(candidate);
+		}
+	}
+
+	/// Bring the trie up to date with all the writes so far.
+	fn sync_trie(&mut self) {
+		self.check_external_writes();
+		if self.trie_stale {
+			self.trie.clear();
+			self.trie_dirty.clear();
+			let mut from = Unbounded;
+			while let Some(key) = self.next_key(bound_as_ref(&from)) {
+				let value = self
+					.read(&key)
+					.expect("`next_key` only returns set keys; qed");
+				self.trie.insert(&key, blake2_256(&value));
+				from = Excluded(key);
+			}
+			self.trie_stale = false;
+		}
+
+		for key in std::mem::take(&mut self.trie_dirty) {
+			match self.read(&key) {
+				Some(value) => self.trie.insert(&key, blake2_256(&value)),
+				None => self.trie.remove(&key),
+			}
+		}
+	}
+
+	/// Record the current value of `key` in the history, if this is its first write in the block.
+	fn note_history(&mut self, key: &[u8]) {
+		if !self.history.is_noted(key) {
+			let previous = self.read(key);
+			self.history.note(key, previous);
+		}
+	}
+
+	/// Write `maybe_value` under `key` to the top-most layer, or the backend if no transaction is
+	/// open.
+	fn write(&mut self, key: Key, maybe_value: Option<Value>) {
+		self.trie_dirty.insert(key.clone());
+		match self.transactions.last_mut() {
+			Some(overlay) => {
+				overlay.insert(key, maybe_value);
+			}
+			None => {
+				self.cache.write(&key, &maybe_value);
+				match maybe_value {
+					Some(value) => self.backend.set(key, value),
+					None => self.backend.clear(&key),
+				}
+			}
+		}
+	}
+
+	/// Let `f` change the value under `key` in the top-most layer, or the backend if no
+	/// transaction is open, in place where possible.
+	///
+	/// `f` may be called twice, once for the backend and once for the cache, and must thus make
+	/// the same change both times.
+	fn update(&mut self, key: &[u8], mut f: impl FnMut(&mut Option<Value>)) {
+		self.trie_dirty.insert(key.to_vec());
+		if self.transactions.is_empty() {
+			self.backend.update_with(key, &mut f);
+			// the cached value, if any, is the same as the one in the backend.
+			return self.cache.update(key, f);
+		}
+
+		// the value is copied up into the top-most layer once, and changed in place from then on.
+		let top = self
+			.transactions
+			.last()
+			.expect("checked to be non-empty above; qed");
+		let mut maybe_value = if top.contains_key(key) {
+			None
+		} else {
+			self.read(key)
+		};
+		let top = self
+			.transactions
+			.last_mut()
+			.expect("checked to be non-empty above; qed");
+		f(top
+			.entry(key.to_vec())
+			.or_insert_with(|| maybe_value.take()))
+	}
+}
+
+/// Add `item` to the compact-length-prefixed sequence in `maybe_value`, or create a sequence of
+/// just `item` if there is no value. Anything else is left untouched.
+fn append_item(maybe_value: &mut Option<Value>, item: &[u8]) {
+	match maybe_value {
+		Some(value) => {
+			let Some((len, new_len)) = Compact::<u32>::decode(&mut &value[..])
+				.ok()
+				.and_then(|Compact(len)| Some((len, len.checked_add(1)?)))
+			else {
+				return;
+			};
+			let prefix_len = Compact::<u32>::compact_len(&len);
+			let new_prefix = Compact(new_len).encode();
+			if new_prefix.len() == prefix_len {
+				value[..prefix_len].copy_from_slice(&new_prefix);
+			} else {
+				// the compact length only takes more bytes as it reaches 2^6, 2^14 and 2^30.
+				value.splice(..prefix_len, new_prefix);
+			}
+			value.extend_from_slice(item);
+		}
+		None => *maybe_value = Some([&Compact(1u32).encode(), item].concat()),
+	}
+}
+
+thread_local! {
+	static STATE: RefCell<State> = RefCell::new(State::default());
+}
+
+/// Install `backend` as the storage backend of the current thread, returning the previous one.
+///
+/// Any open transactions are left untouched, and will be committed into the new backend.
+///
+/// ```
+/// # use pba_pre_course_assignment::l_mini_substrate::io_storage::{self, InMemoryBackend};
+/// io_storage::set(b"key", vec![1]);
+///
+/// let previous = io_storage::set_backend(Box::new(InMemoryBackend::default()));
+/// assert_eq!(io_storage::get(b"key"), None);
+///
+/// io_storage::set_backend(previous);
+/// assert_eq!(io_storage::get(b"key"), Some(vec![1]));
+/// ```
+pub fn set_backend(backend: Box<dyn StorageBackend>) -> Box<dyn StorageBackend> {
+	STATE.with(|s| {
+		let mut state = s.borrow_mut();
+		state.trie_stale = true;
+		state.cache.clear();
+		std::mem::replace(&mut state.backend, backend)
+	})
+}
+
+/// Make all the writes that have reached the backend permanent. See [`StorageBackend::commit`].
+///
+/// Writes that are still inside an open transaction are not affected. Errors of the backend, e.g.
+/// failing to write to disk, are returned as is.
+pub fn commit() -> std::io::Result<()> {
+	STATE.with(|s| s.borrow_mut().backend.commit())
+}
+
+/// The errors that can occur when handling transactions.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum TransactionError {
+	/// A commit or rollback was requested, but no transaction is open.
+	NoOpenTransaction,
+}
+
+/// Get the value under `key`.
+///
+/// This returns a copy of the value. Use [`read_with`] to look at it in place instead.
+pub fn get(key: impl AsRef<[u8]>) -> Option<Value> {
+	read_with(key, |maybe_value| maybe_value.map(<[u8]>::to_vec))
+}
+
+/// Call `f` with the value under `key`, without copying it, and return whatever `f` returns.
+///
+/// This is the cheapest way to read a value, e.g. to decode it straight from storage. `f` must not
+/// access the storage itself.
+///
+/// ```
+/// # use pba_pre_course_assignment::l_mini_substrate::io_storage;
+/// io_storage::set(b"key", vec![1, 2, 3]);
+/// assert_eq!(io_storage::read_with(b"key", |value| value.map(|v| v.len())), Some(3));
+/// assert_eq!(io_storage::read_with(b"other", |value| value.is_none()), true);
+/// ```
+pub fn read_with<R>(key: impl AsRef<[u8]>, f: impl FnOnce(Option<&[u8]>) -> R) -> R {
+	STATE.with(|s| s.borrow_mut().read_with(key.as_ref(), f))
+}
+
+/// Whether a value exists under `key`.
+pub fn exists(key: impl AsRef<[u8]>) -> bool {
+	read_with(key, |maybe_value| maybe_value.is_some())
+}
+
+/// Set the value under `key` to `value`.
+pub fn set(key: impl AsRef<[u8]>, value: Value) {
+	let key = key.as_ref();
+	STATE.with(|s| {
+		let mut state = s.borrow_mut();
+		state.meter.write(value.len());
+		state.note_history(key);
+		state.write(key.to_vec(), Some(value))
+	});
+}
+
+/// Append the already encoded `item` to the SCALE encoded sequence, e.g. a `Vec`, under `key`.
+///
+/// The compact length that prefixes the sequence is updated in place, and `item` is added at the
+/// end, such that the cost does not depend on the number of items already there. If there is no
+/// value under `key`, a sequence of just `item` is created. A value that does not start with a
+/// compact length is left untouched, as it is most likely corrupt.
+///
+/// ```
+/// # use parity_scale_codec::{Decode, Encode};
+/// # use pba_pre_course_assignment::l_mini_substrate::io_storage;
+/// io_storage::append(b"key", &1u32.encode());
+/// io_storage::append(b"key", &2u32.encode());
+///
+/// let value = io_storage::get(b"key").unwrap();
+/// assert_eq!(Vec::<u32>::decode(&mut &*value), Ok(vec![1, 2]));
+/// ```
+pub fn append(key: impl AsRef<[u8]>, item: &[u8]) {
+	let key = key.as_ref();
+	STATE.with(|s| {
+		let mut state = s.borrow_mut();
+		state.meter.write(item.len());
+		state.note_history(key);
+		state.update(key, |maybe_value| append_item(maybe_value, item))
+	});
+}
+
+/// Remove the value under `key`.
+pub fn clear(key: impl AsRef<[u8]>) {
+	let key = key.as_ref();
+	STATE.with(|s| {
+		let mut state = s.borrow_mut();
+		state.meter.write(0);
+		state.note_history(key);
+		state.write(key.to_vec(), None)
+	});
+}
+
+/// Get the first key that is strictly greater than `key`, in lexicographic order.
+pub fn next_key(key: &[u8]) -> Option<Key> {
+	STATE.with(|s| {
+		let mut state = s.borrow_mut();
+		state.meter.read(0);
+		state.next_key(Excluded(key))
+	})
+}
+
+/// Iterate over all key-value pairs whose key starts with `prefix`, in lexicographic order of
+/// keys.
+///
+/// The iterator does not borrow the storage, so it is fine to write to the storage while
+/// iterating. Each step looks for the next key after the last one that was returned.
+///
+/// ```
+/// # use pba_pre_course_assignment::l_mini_substrate::io_storage;
+/// io_storage::set(b"map_b", vec![2]);
+/// io_storage::set(b"map_a", vec![1]);
+/// io_storage::set(b"other", vec![3]);
+///
+/// assert_eq!(
+///     io_storage::iter_prefix(b"map_").collect::<Vec<_>>(),
+///     vec![(b"map_a".to_vec(), vec![1]), (b"map_b".to_vec(), vec![2])],
+/// );
+/// ```
+pub fn iter_prefix(prefix: &[u8]) -> PrefixIterator {
+	PrefixIterator {
+		prefix: prefix.to_vec(),
+		previous_key: None,
+	}
+}
+
+/// Remove at most `limit` keys that start with `prefix`, or all of them if `limit` is `None`.
+///
+/// Returns the number of keys removed, and the next key that still starts with `prefix`, if
+/// any. A `Some(_)` cursor means that the call should be repeated to finish the job.
+pub fn clear_prefix(prefix: &[u8], limit: Option<u32>) -> (u32, Option<Key>) {
+	let mut removed = 0;
+	let mut keys = iter_prefix(prefix).map(|(key, _)| key);
+	while limit.map_or(true, |limit| removed < limit) {
+		match keys.next() {
+			Some(key) => {
+				clear(key);
+				removed += 1;
+			}
+			None => return (removed, None),
+		}
+	}
+	(removed, keys.next())
+}
+
+/// An iterator over all key-value pairs under a prefix. See [`iter_prefix`].
+pub struct PrefixIterator {
+	prefix: Key,
+	previous_key: Option<Key>,
+}
+
+impl Iterator for PrefixIterator {
+	type Item = (Key, Value);
+
+	fn next(&mut self) -> Option<Self::Item> {
+		STATE.with(|s| {
+			let mut state = s.borrow_mut();
+			let from = match self.previous_key {
+				Some(ref previous) => Excluded(previous.as_slice()),
+				None => Included(self.prefix.as_slice()),
+			};
+			let key = state
+				.next_key(from)
+				.filter(|key| key.starts_with(&self.prefix))?;
+			let value = state
+				.read(&key)
+				.expect("`next_key` only returns set keys; qed");
+			state.meter.read(value.len());
+			self.previous_key = Some(key.clone());
+			Some((key, value))
+		})
+	}
+}
+
+/// Open a new transactional layer. All writes from now on can be reverted by a matching
+/// [`rollback_transaction`].
+pub fn start_transaction() {
+	STATE.with(|s| s.borrow_mut().transactions.push(Overlay::new()));
+}
+
+/// Keep all the writes of the top-most transaction, merging them into its parent.
+pub fn commit_transaction() -> Result<(), TransactionError> {
+	STATE.with(|s| {
+		let mut state = s.borrow_mut();
+		let overlay = state
+			.transactions
+			.pop()
+			.ok_or(TransactionError::NoOpenTransaction)?;
+		for (key, maybe_value) in overlay {
+			state.write(key, maybe_value);
+		}
+		Ok(())
+	})
+}
+
+/// Discard all the writes of the top-most transaction.
+pub fn rollback_transaction() -> Result<(), TransactionError> {
+	STATE.with(|s| {
+		let mut state = s.borrow_mut();
+		let overlay = state
+			.transactions
+			.pop()
+			.ok_or(TransactionError::NoOpenTransaction)?;
+		// the keys of the overlay are now back to their previous values.
+		state.trie_dirty.extend(overlay.into_keys());
+		Ok(())
+	})
+}
+
+/// The Merkle root of all the data in storage, including the writes of any open transactions.
+///
+/// Two storages with the same root hold exactly the same data. The root is maintained
+/// incrementally: only the keys that have been written since the last call are rehashed, along
+/// with the trie nodes on their path.
+///
+/// ```
+/// # use pba_pre_course_assignment::l_mini_substrate::io_storage;
+/// let empty = io_storage::storage_root();
+///
+/// io_storage::set(b"key", vec![1]);
+/// let root = io_storage::storage_root();
+/// assert_ne!(root, empty);
+///
+/// io_storage::clear(b"key");
+/// assert_eq!(io_storage::storage_root(), empty);
+/// ```
+pub fn storage_root() -> Hash {
+	STATE.with(|s| {
+		let mut state = s.borrow_mut();
+		state.sync_trie();
+		state.trie.root()
+	})
+}
+
+/// The number of currently open transactions.
+pub fn transaction_depth() -> usize {
+	STATE.with(|s| s.borrow().transactions.len())
+}
+
+/// Execute `f` in a new transaction. The transaction is committed if `f` returns `Ok`, and
+/// rolled back otherwise.
+///
+/// ```
+/// # use pba_pre_course_assignment::l_mini_substrate::io_storage;
+/// io_storage::set(b"key", vec![1]);
+///
+/// let result: Result<(), &str> = io_storage::with_transaction(|| {
+///     io_storage::set(b"key", vec![2]);
+///     Err("something went wrong")
+/// });
+///
+/// assert!(result.is_err());
+/// assert_eq!(io_storage::get(b"key"), Some(vec![1]));
+/// ```
+pub fn with_transaction<R, E>(f: impl FnOnce() -> Result<R, E>) -> Result<R, E> {
+	start_transaction();
+	let result = f();
+	let outcome = match result {
+		Ok(_) => commit_transaction(),
+		Err(_) => rollback_transaction(),
+	};
+	// `f` is allowed to open and close its own transactions, but it must leave them balanced.
+	outcome.expect("transaction opened by `with_transaction` must still exist; qed");
+	result
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn read_with_sees_all_layers() {
+		let len = |key: &[u8]| read_with(key, |value| value.map(<[u8]>::len));
+		set(b"backend", vec![0; 3]);
+		start_transaction();
+		set(b"overlay", vec![0; 5]);
+		clear(b"backend");
+		assert_eq!(len(b"overlay"), Some(5));
+		assert_eq!(len(b"backend"), None);
+		assert!(!exists(b"backend"));
+		rollback_transaction().unwrap();
+
+		// both through the cache, and straight from the backend.
+		assert_eq!(len(b"backend"), Some(3));
+		set_cache_capacity(0);
+		assert_eq!(len(b"backend"), Some(3));
+		assert!(exists(b"backend"));
+		assert!(!exists(b"overlay"));
+	}
+
+	#[test]
+	fn rollback_reverts_writes() {
+		set(b"a", vec![1]);
+
+		start_transaction();
+		set(b"a", vec![2]);
+		set(b"b", vec![3]);
+		assert_eq!(get(b"a"), Some(vec![2]));
+		assert_eq!(get(b"b"), Some(vec![3]));
+		rollback_transaction().unwrap();
+
+		assert_eq!(get(b"a"), Some(vec![1]));
+		assert_eq!(get(b"b"), None);
+	}
+
+	#[test]
+	fn commit_keeps_writes_and_clears() {
+		set(b"a", vec![1]);
+
+		start_transaction();
+		clear(b"a");
+		set(b"b", vec![2]);
+		assert_eq!(get(b"a"), None);
+		commit_transaction().unwrap();
+
+		assert_eq!(transaction_depth(), 0);
+		assert_eq!(get(b"a"), None);
+		assert_eq!(get(b"b"), Some(vec![2]));
+	}
+
+	#[test]
+	fn nested_transactions() {
+		start_transaction();
+		set(b"outer", vec![1]);
+
+		start_transaction();
+		set(b"inner", vec![2]);
+		commit_transaction().unwrap();
+
+		start_transaction();
+		set(b"discarded", vec![3]);
+		clear(b"outer");
+		rollback_transaction().unwrap();
+
+		assert_eq!(get(b"outer"), Some(vec![1]));
+		assert_eq!(get(b"inner"), Some(vec![2]));
+		assert_eq!(get(b"discarded"), None);
+
+		// dropping the outer one drops everything, including the committed inner one.
+		rollback_transaction().unwrap();
+		assert_eq!(get(b"outer"), None);
+		assert_eq!(get(b"inner"), None);
+	}
+
+	#[test]
+	fn unbalanced_commit_or_rollback_fails() {
+		assert_eq!(
+			commit_transaction(),
+			Err(TransactionError::NoOpenTransaction)
+		);
+		assert_eq!(
+			rollback_transaction(),
+			Err(TransactionError::NoOpenTransaction)
+		);
+	}
+
+	#[test]
+	fn with_transaction_works() {
+		let ok: Result<u32, ()> = with_transaction(|| {
+			set(b"a", vec![1]);
+			Ok(42)
+		});
+		assert_eq!(ok, Ok(42));
+		assert_eq!(get(b"a"), Some(vec![1]));
+
+		let err: Result<(), ()> = with_transaction(|| {
+			set(b"a", vec![2]);
+			let _ = with_transaction(|| -> Result<(), ()> {
+				set(b"b", vec![3]);
+				Ok(())
+			});
+			Err(())
+		});
+		assert_eq!(err, Err(()));
+		assert_eq!(get(b"a"), Some(vec![1]));
+		assert_eq!(get(b"b"), None);
+		assert_eq!(transaction_depth(), 0);
+	}
+
+	#[test]
+	fn next_key_sees_through_transactions() {
+		set(b"a", vec![]);
+		set(b"c", vec![]);
+		set(b"e", vec![]);
+
+		start_transaction();
+		set(b"b", vec![]);
+		clear(b"c");
+		start_transaction();
+		set(b"d", vec![]);
+
+		assert_eq!(next_key(b""), Some(b"a".to_vec()));
+		assert_eq!(next_key(b"a"), Some(b"b".to_vec()));
+		assert_eq!(next_key(b"b"), Some(b"d".to_vec()));
+		assert_eq!(next_key(b"d"), Some(b"e".to_vec()));
+		assert_eq!(next_key(b"e"), None);
+
+		rollback_transaction().unwrap();
+		rollback_transaction().unwrap();
+		assert_eq!(next_key(b"a"), Some(b"c".to_vec()));
+	}
+
+	#[test]
+	fn iter_prefix_works() {
+		set(b"map", vec![0]);
+		set(b"map1", vec![1]);
+		set(b"map2", vec![2]);
+		set(b"mao", vec![9]);
+		set(b"maq", vec![9]);
+
+		assert_eq!(
+			iter_prefix(b"map").collect::<Vec<_>>(),
+			vec![
+				(b"map".to_vec(), vec![0]),
+				(b"map1".to_vec(), vec![1]),
+				(b"map2".to_vec(), vec![2])
+			]
+		);
+		assert_eq!(iter_prefix(b"none").count(), 0);
+		assert_eq!(iter_prefix(b"").count(), 5);
+	}
+
+	#[test]
+	fn clear_prefix_in_chunks() {
+		for i in 0..5u8 {
+			set([b"map".as_ref(), &[i]].concat(), vec![i]);
+		}
+		set(b"other", vec![]);
+
+		assert_eq!(
+			clear_prefix(b"map", Some(2)),
+			(2, Some(b"map\x02".to_vec()))
+		);
+		assert_eq!(iter_prefix(b"map").count(), 3);
+		assert_eq!(clear_prefix(b"map", Some(3)), (3, None));
+		assert_eq!(clear_prefix(b"map", None), (0, None));
+		assert_eq!(get(b"other"), Some(vec![]));
+	}
+
+	#[test]
+	fn clear_prefix_can_be_rolled_back() {
+		set(b"map1", vec![1]);
+		set(b"map2", vec![2]);
+
+		let _ = with_transaction(|| -> Result<(), ()> {
+			assert_eq!(clear_prefix(b"map", None), (2, None));
+			assert_eq!(iter_prefix(b"map").count(), 0);
+			Err(())
+		});
+		assert_eq!(iter_prefix(b"map").count(), 2);
+	}
+
+	#[test]
+	fn append_extends_encoded_sequences() {
+		let decoded = |key: &[u8]| Vec::<u16>::decode(&mut &*get(key).unwrap()).unwrap();
+		// the length takes a second byte once it reaches 64.
+		for i in 0..70u16 {
+			append(b"seq", &i.encode());
+		}
+		assert_eq!(decoded(b"seq"), (0..70).collect::<Vec<_>>());
+
+		// the cached value is kept up to date as well.
+		append(b"seq", &70u16.encode());
+		assert_eq!(decoded(b"seq"), (0..71).collect::<Vec<_>>());
+
+		// anything that is not a sequence is left untouched.
+		set(b"other", vec![]);
+		append(b"other", &1u16.encode());
+		assert_eq!(get(b"other"), Some(vec![]));
+	}
+
+	#[test]
+	fn append_widens_the_length_prefix() {
+		use crate::l_mini_substrate::bounded::DecodeLength;
+		set(b"seq", (0..63u32).collect::<Vec<_>>().encode());
+		assert_eq!(get(b"seq").unwrap()[0], 63 << 2);
+
+		append(b"seq", &63u32.encode());
+		let value = get(b"seq").unwrap();
+		// 64 no longer fits in a single byte.
+		assert_eq!(&value[..2], &Compact(64u32).encode()[..]);
+		assert_eq!(Vec::<u32>::decode_len(&value), Ok(64));
+		assert_eq!(Vec::<u32>::decode(&mut &*value), Ok((0..64).collect()));
+	}
+
+	#[test]
+	fn append_can_be_rolled_back() {
+		set(b"seq", vec![1u8].encode());
+		let _ = with_transaction(|| -> Result<(), ()> {
+			append(b"seq", &[2]);
+			with_transaction(|| -> Result<(), ()> {
+				append(b"seq", &[3]);
+				Ok(())
+			})?;
+			assert_eq!(get(b"seq"), Some(vec![1u8, 2, 3].encode()));
+			Err(())
+		});
+		assert_eq!(get(b"seq"), Some(vec![1u8].encode()));
+
+		with_transaction(|| -> Result<(), ()> {
+			append(b"seq", &[2]);
+			Ok(())
+		})
+		.unwrap();
+		assert_eq!(get(b"seq"), Some(vec![1u8, 2].encode()));
+	}
+
+	/// A backend that wraps an in-memory one, and counts how many times it has been committed.
+	#[derive(Default)]
+	struct CountingBackend {
+		inner: InMemoryBackend,
+		commits: std::rc::Rc<std::cell::Cell<u32>>,
+	}
+
+	impl StorageBackend for CountingBackend {
+		fn get(&self, key: &[u8]) -> Option<Value> {
+			self.inner.get(key)
+		}
+		fn set(&mut self, key: Key, value: Value) {
+			self.inner.set(key, value)
+		}
+		fn clear(&mut self, key: &[u8]) {
+			self.inner.clear(key)
+		}
+		fn next_key(&self, from: Bound<&[u8]>) -> Option<Key> {
+			self.inner.next_key(from)
+		}
+		fn commit(&mut self) -> std::io::Result<()> {
+			self.commits.set(self.commits.get() + 1);
+			Ok(())
+		}
+	}
+
+	#[test]
+	fn custom_backend_can_be_installed() {
+		use crate::l_mini_substrate::shared::{OptionQuery, StorageValue};
+		struct Dummy;
+		impl StorageValue for Dummy {
+			type Value = u32;
+			type QueryKind = OptionQuery;
+			fn module_prefix() -> &'static str {
+				"Test"
+			}
+			fn storage_prefix() -> &'static str {
+				"Dummy"
+			}
+		}
+
+		Dummy::set(1);
+		let backend = CountingBackend::default();
+		let commits = backend.commits.clone();
+		let default = set_backend(Box::new(backend));
+
+		// the new backend starts empty, and typed storage works on top of it.
+		assert_eq!(Dummy::get(), None);
+		Dummy::set(2);
+		let _ = with_transaction(|| -> Result<(), ()> {
+			Dummy::mutate(|v| *v = v.map(|v| v + 1));
+			set(b"other", vec![]);
+			Ok(())
+		});
+		assert_eq!(Dummy::get(), Some(3));
+		assert_eq!(iter_prefix(b"").count(), 2);
+
+		commit().unwrap();
+		assert_eq!(commits.get(), 1);
+
+		// and the old data is back once the default backend is re-installed.
+		set_backend(default);
+		assert_eq!(Dummy::get(), Some(1));
+	}
+
+	#[test]
+	fn backend_iter_works() {
+		let backend: InMemoryBackend = vec![(b"b".to_vec(), vec![2]), (b"a".to_vec(), vec![1])]
+			.into_iter()
+			.collect();
+		let counting = CountingBackend {
+			inner: backend.clone(),
+			..Default::default()
+		};
+
+		let expected = vec![(b"a".to_vec(), vec![1]), (b"b".to_vec(), vec![2])];
+		assert_eq!(backend.iter().collect::<Vec<_>>(), expected);
+		// the default implementation, based on `next_key`.
+		assert_eq!(counting.iter().collect::<Vec<_>>(), expected);
+	}
+
+	#[test]
+	fn storage_root_follows_transactions() {
+		set(b"a", vec![1]);
+		let root = storage_root();
+
+		start_transaction();
+		set(b"b", vec![2]);
+		let with_b = storage_root();
+		assert_ne!(with_b, root);
+		rollback_transaction().unwrap();
+		assert_eq!(storage_root(), root);
+
+		let _ = with_transaction(|| -> Result<(), ()> {
+			set(b"b", vec![2]);
+			Ok(())
+		});
+		assert_eq!(storage_root(), with_b);
+	}
+
+	#[test]
+	fn storage_root_depends_only_on_data() {
+		set(b"a", vec![1]);
+		set(b"b", vec![2]);
+		set(b"c", vec![3]);
+		clear(b"b");
+		let root = storage_root();
+
+		// the same data, written differently, into a fresh backend.
+		let backend: InMemoryBackend = vec![(b"c".to_vec(), vec![3])].into_iter().collect();
+		let previous = set_backend(Box::new(backend));
+		assert_ne!(storage_root(), root);
+		set(b"a", vec![1]);
+		assert_eq!(storage_root(), root);
+
+		set_backend(previous);
+		assert_eq!(storage_root(), root);
+	}
+}
@@ -0,0 +1,166 @@
+//! The backends that can sit behind [`io_storage`](super).
+//!
+//! A backend is the place where the data of `io_storage` actually lives, once it has left any open
+//! transaction. Anything that implements [`StorageBackend`] can be installed for the current thread
+//! via [`super::set_backend`]. By default, each thread uses an [`InMemoryBackend`].
+
+use super::{Key, Value};
+use std::{
+	collections::BTreeMap,
+	io,
+	ops::Bound::{self, Excluded, Included, Unbounded},
+};
+
+/// Something that can store the raw key-value pairs of `io_storage`.
+///
+/// Keys must be kept in lexicographic order, as [`StorageBackend::next_key`] is what powers all
+/// the iteration in `io_storage`.
+pub trait StorageBackend {
+	/// Get the value under `key`.
+	fn get(&self, key: &[u8]) -> Option<Value>;
+
+	/// Call `f` with the value under `key`, without copying it if the backend can avoid it.
+	///
+	/// By default, this goes through [`StorageBackend::get`].
+	fn get_with(&self, key: &[u8], f: &mut dyn FnMut(Option<&[u8]>)) {
+		f(self.get(key).as_deref())
+	}
+
+	/// Set the value under `key` to `value`.
+	fn set(&mut self, key: Key, value: Value);
+
+	/// Remove the value under `key`. noop if nothing exists.
+	fn clear(&mut self, key: &[u8]);
+
+	/// Let `f` change the value under `key`, in place if the backend can do so. `None` stands for
+	/// the absence of a value, both before and after `f`.
+	///
+	/// By default, this goes through [`StorageBackend::get`], and then [`StorageBackend::set`] or
+	/// [`StorageBackend::clear`].
+	fn update_with(&mut self, key: &[u8], f: &mut dyn FnMut(&mut Option<Value>)) {
+		let mut maybe_value = self.get(key);
+		f(&mut maybe_value);
+		match maybe_value {
+			Some(value) => self.set(key.to_vec(), value),
+			None => self.clear(key),
+		}
+	}
+
+	/// The first key within `(from, ..)` that has a value, if any.
+	fn next_key(&self, from: Bound<&[u8]>) -> Option<Key>;
+
+	/// Make all writes so far permanent.
+	///
+	/// This is a noop for backends that are not persistent. If it fails, the writes are still
+	/// there, and will be made permanent by the next successful commit.
+	fn commit(&mut self) -> io::Result<()> {
+		Ok(())
+	}
+
+	/// Whether the data has been modified by anything else than this instance since the last
+	/// call, e.g. by another thread sharing the same data.
+	///
+	/// If so, everything `io_storage` derives from the data, such as the storage root, is
+	/// recomputed from scratch. This is always `false` for backends that are not shared.
+	fn has_external_writes(&mut self) -> bool {
+		false
+	}
+
+	/// Iterate over all the key-value pairs, in order of keys.
+	fn iter(&self) -> Box<dyn Iterator<Item = (Key, Value)> + '_> {
+		let mut from = Unbounded;
+		Box::new(std::iter::from_fn(move || {
+			let key = self.next_key(bound_as_ref(&from))?;
+			let value = self
+				.get(&key)
+				.expect("`next_key` only returns set keys; qed");
+			from = Excluded(key.clone());
+			Some((key, value))
+		}))
+	}
+}
+
+/// `Bound::as_ref`, but for `Vec<u8>` to `&[u8]`.
+pub(crate) fn bound_as_ref(bound: &Bound<Key>) -> Bound<&[u8]> {
+	match bound {
+		Included(key) => Included(key.as_slice()),
+		Excluded(key) => Excluded(key.as_slice()),
+		Unbounded => Unbounded,
+	}
+}
+
+/// [`StorageBackend::get_with`], for a `f` that is only called once and returns a value.
+pub(crate) fn read_with<R>(
+	backend: &dyn StorageBackend,
+	key: &[u8],
+	f: impl FnOnce(Option<&[u8]>) -> R,
+) -> R {
+	let mut f = Some(f);
+	let mut result = None;
+	backend.get_with(key, &mut |maybe_value| {
+		let f = f.take().expect("backends call `f` exactly once; qed");
+		result = Some(f(maybe_value));
+	});
+	result.expect("backends call `f` exactly once; qed")
+}
+
+/// The default backend, a simple in-memory ordered map.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct InMemoryBackend(BTreeMap<Key, Value>);
+
+impl InMemoryBackend {
+	/// Consume `self`, returning all the data.
+	pub fn into_inner(self) -> BTreeMap<Key, Value> {
+		self.0
+	}
+}
+
+impl From<BTreeMap<Key, Value>> for InMemoryBackend {
+	fn from(map: BTreeMap<Key, Value>) -> Self {
+		Self(map)
+	}
+}
+
+impl FromIterator<(Key, Value)> for InMemoryBackend {
+	fn from_iter<I: IntoIterator<Item = (Key, Value)>>(iter: I) -> Self {
+		Self(iter.into_iter().collect())
+	}
+}
+
+impl StorageBackend for InMemoryBackend {
+	fn get(&self, key: &[u8]) -> Option<Value> {
+		self.0.get(key).cloned()
+	}
+
+	fn get_with(&self, key: &[u8], f: &mut dyn FnMut(Option<&[u8]>)) {
+		f(self.0.get(key).map(Vec::as_slice))
+	}
+
+	fn set(&mut self, key: Key, value: Value) {
+		self.0.insert(key, value);
+	}
+
+	fn clear(&mut self, key: &[u8]) {
+		self.0.remove(key);
+	}
+
+	fn update_with(&mut self, key: &[u8], f: &mut dyn FnMut(&mut Option<Value>)) {
+		let mut maybe_value = self.0.get_mut(key).map(std::mem::take);
+		f(&mut maybe_value);
+		match maybe_value {
+			Some(value) => self.set(key.to_vec(), value),
+			None => self.clear(key),
+		}
+	}
+
+	fn next_key(&self, from: Bound<&[u8]>) -> Option<Key> {
+		self.0
+			.range::<[u8], _>((from, Unbounded))
+			.next()
+			.map(|(k, _)| k.clone())
+	}
+
+	fn iter(&self) -> Box<dyn Iterator<Item = (Key, Value)> + '_> {
+		Box::new(self.0.iter().map(|(k, v)| (k.clone(), v.clone())))
+	}
+}
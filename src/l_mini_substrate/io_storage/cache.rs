@@ -0,0 +1,317 @@
+//! A read cache in front of the storage backend.
+//!
+//! Runtime code tends to read the same keys over and over within a dispatch, e.g. `exists`, `get`
+//! and then `mutate` on the same `StorageMap` entry. Values read by [`super::get`] and
+//! [`super::read_with`] from the backend are thus kept in a bounded, least-recently-used cache, and
+//! served from there the next time, without any copy in the case of [`super::read_with`].
+//!
+//! The cache only ever holds what is in the backend: writes that reach the backend update the
+//! cached entry as well (write-through), while writes that are still inside a transaction never
+//! touch the cache, as they are served from their overlay anyway. Replacing the backend, or a
+//! backend reporting writes from elsewhere, empties the cache.
+//!
+//! The size of the cache is set with [`set_cache_capacity`], and how well it does is reported by
+//! [`cache_stats`].
+
+use super::{
+	backend::{read_with, StorageBackend},
+	Key, Value, STATE,
+};
+use std::collections::HashMap;
+
+/// The number of entries that are cached by default.
+pub const DEFAULT_CACHE_CAPACITY: usize = 1024;
+
+/// The hit and miss counters of the read cache.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct CacheStats {
+	/// The number of reads that were served from the cache.
+	pub hits: u64,
+	/// The number of reads that had to go to the backend.
+	pub misses: u64,
+}
+
+/// Marks the absence of a slot in the recency list.
+const NONE: usize = usize::MAX;
+
+/// A cached value, and its neighbours in the recency list.
+#[derive(Debug)]
+struct Slot {
+	key: Key,
+	value: Option<Value>,
+	/// The slot that was used right after this one.
+	newer: usize,
+	/// The slot that was used right before this one.
+	older: usize,
+}
+
+/// A bounded LRU cache of backend values, including the absence of a value.
+///
+/// The slots form a doubly linked list, from the most to the least recently used, such that both
+/// a hit and an eviction are constant time.
+#[derive(Debug)]
+pub(crate) struct ReadCache {
+	capacity: usize,
+	/// The index of each cached key in `slots`.
+	index: HashMap<Key, usize>,
+	slots: Vec<Slot>,
+	newest: usize,
+	oldest: usize,
+	stats: CacheStats,
+}
+
+impl Default for ReadCache {
+	fn default() -> Self {
+		Self::new(DEFAULT_CACHE_CAPACITY)
+	}
+}
+
+impl ReadCache {
+	fn new(capacity: usize) -> Self {
+		Self {
+			capacity,
+			index: HashMap::new(),
+			slots: Vec::new(),
+			newest: NONE,
+			oldest: NONE,
+			stats: CacheStats::default(),
+		}
+	}
+
+	/// Call `f` with the value under `key`, reading it from `backend` if it is not cached yet.
+	pub(crate) fn read_with<R>(
+		&mut self,
+		key: &[u8],
+		backend: &dyn StorageBackend,
+		f: impl FnOnce(Option<&[u8]>) -> R,
+	) -> R {
+		if let Some(&slot) = self.index.get(key) {
+			self.stats.hits += 1;
+			self.unlink(slot);
+			self.push_newest(slot);
+			return f(self.slots[slot].value.as_deref());
+		}
+
+		self.stats.misses += 1;
+		if self.capacity == 0 {
+			return read_with(backend, key, f);
+		}
+		let slot = if self.slots.len() < self.capacity {
+			self.slots.push(Slot {
+				key: key.to_vec(),
+				value: None,
+				newer: NONE,
+				older: NONE,
+			});
+			self.slots.len() - 1
+		} else {
+			// reuse the slot of the least recently used value.
+			let slot = self.oldest;
+			self.unlink(slot);
+			self.index.remove(&self.slots[slot].key);
+			self.slots[slot].key = key.to_vec();
+			slot
+		};
+		self.slots[slot].value = backend.get(key);
+		self.index.insert(key.to_vec(), slot);
+		self.push_newest(slot);
+		f(self.slots[slot].value.as_deref())
+	}
+
+	/// The backend value under `key` is now `maybe_value`. Update the cached entry, if any.
+	pub(crate) fn write(&mut self, key: &[u8], maybe_value: &Option<Value>) {
+		if let Some(&slot) = self.index.get(key) {
+			self.slots[slot].value.clone_from(maybe_value);
+		}
+	}
+
+	/// Let `f` change the cached value under `key` in place, if any, the same way the backend value
+	/// has just been changed.
+	pub(crate) fn update(&mut self, key: &[u8], f: impl FnOnce(&mut Option<Value>)) {
+		if let Some(&slot) = self.index.get(key) {
+			f(&mut self.slots[slot].value)
+		}
+	}
+
+	/// Forget everything, e.g. because the backend has changed.
+	pub(crate) fn clear(&mut self) {
+		self.index.clear();
+		self.slots.clear();
+		self.newest = NONE;
+		self.oldest = NONE;
+	}
+
+	/// Only keep up to `capacity` values, evicting the least recently used ones if needed.
+	fn set_capacity(&mut self, capacity: usize) {
+		self.capacity = capacity;
+		if self.slots.len() <= capacity {
+			return;
+		}
+
+		let mut kept = Vec::with_capacity(capacity);
+		let mut slot = self.newest;
+		while kept.len() < capacity {
+			kept.push(slot);
+			slot = self.slots[slot].older;
+		}
+		let mut slots = std::mem::take(&mut self.slots)
+			.into_iter()
+			.map(Some)
+			.collect::<Vec<_>>();
+		self.clear();
+		// re-insert the kept values from the oldest to the newest.
+		for slot in kept.into_iter().rev() {
+			let Slot { key, value, .. } = slots[slot].take().expect("each slot is kept once; qed");
+			self.index.insert(key.clone(), self.slots.len());
+			self.slots.push(Slot {
+				key,
+				value,
+				newer: NONE,
+				older: NONE,
+			});
+			self.push_newest(self.slots.len() - 1);
+		}
+	}
+
+	/// Remove `slot` from the recency list.
+	fn unlink(&mut self, slot: usize) {
+		let Slot { newer, older, .. } = self.slots[slot];
+		match newer {
+			NONE => self.newest = older,
+			newer => self.slots[newer].older = older,
+		}
+		match older {
+			NONE => self.oldest = newer,
+			older => self.slots[older].newer = newer,
+		}
+	}
+
+	/// Insert `slot` at the front of the recency list.
+	fn push_newest(&mut self, slot: usize) {
+		self.slots[slot].newer = NONE;
+		self.slots[slot].older = self.newest;
+		match self.newest {
+			NONE => self.oldest = slot,
+			newest => self.slots[newest].newer = slot,
+		}
+		self.newest = slot;
+	}
+}
+
+/// Only cache up to `capacity` values from now on, `0` disabling the cache entirely.
+///
+/// The least recently used values are evicted right away if there are more than that.
+pub fn set_cache_capacity(capacity: usize) {
+	STATE.with(|s| s.borrow_mut().cache.set_capacity(capacity))
+}
+
+/// The hits and misses of the read cache since the last [`reset_cache_stats`].
+pub fn cache_stats() -> CacheStats {
+	STATE.with(|s| s.borrow().cache.stats)
+}
+
+/// Reset the hit and miss counters, returning their last value.
+pub fn reset_cache_stats() -> CacheStats {
+	STATE.with(|s| std::mem::take(&mut s.borrow_mut().cache.stats))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::l_mini_substrate::{
+		currency_module,
+		io_storage::{self, get, set, InMemoryBackend, SharedStorage, StorageBackend},
+		runtime::RuntimeCall,
+		shared::{AccountId, Dispatchable},
+	};
+
+	#[test]
+	fn repeated_reads_hit_the_cache() {
+		set(b"a", vec![1]);
+		assert_eq!(get(b"a"), Some(vec![1]));
+		assert_eq!(get(b"a"), Some(vec![1]));
+		// absent values are cached as well.
+		assert_eq!(get(b"b"), None);
+		assert_eq!(get(b"b"), None);
+		assert_eq!(cache_stats(), CacheStats { hits: 2, misses: 2 });
+	}
+
+	#[test]
+	fn writes_go_through_the_cache() {
+		set(b"a", vec![1]);
+		assert_eq!(get(b"a"), Some(vec![1]));
+
+		set(b"a", vec![2]);
+		assert_eq!(get(b"a"), Some(vec![2]));
+
+		io_storage::start_transaction();
+		io_storage::clear(b"a");
+		assert_eq!(get(b"a"), None);
+		io_storage::commit_transaction().unwrap();
+		assert_eq!(get(b"a"), None);
+
+		io_storage::start_transaction();
+		set(b"a", vec![3]);
+		io_storage::rollback_transaction().unwrap();
+		assert_eq!(get(b"a"), None);
+	}
+
+	#[test]
+	fn least_recently_used_is_evicted() {
+		set_cache_capacity(2);
+		for key in [b"a", b"b", b"a", b"c"] {
+			get(key);
+		}
+		reset_cache_stats();
+
+		// `b` was evicted to make room for `c`.
+		get(b"a");
+		get(b"c");
+		assert_eq!(cache_stats(), CacheStats { hits: 2, misses: 0 });
+		get(b"b");
+		assert_eq!(cache_stats(), CacheStats { hits: 2, misses: 1 });
+
+		set_cache_capacity(0);
+		get(b"b");
+		assert_eq!(cache_stats(), CacheStats { hits: 2, misses: 2 });
+	}
+
+	#[test]
+	fn changing_backend_empties_the_cache() {
+		set(b"a", vec![1]);
+		get(b"a");
+		io_storage::set_backend(Box::<InMemoryBackend>::default());
+		assert_eq!(get(b"a"), None);
+
+		let mut shared = SharedStorage::default();
+		io_storage::set_backend(Box::new(shared.clone()));
+		assert_eq!(get(b"a"), None);
+		shared.set(b"a".to_vec(), vec![2]);
+		assert_eq!(get(b"a"), Some(vec![2]));
+	}
+
+	#[test]
+	fn transfers_hit_the_cache() {
+		let alice = AccountId(7);
+		RuntimeCall::Currency(currency_module::Call::Mint {
+			dest: alice,
+			amount: 100,
+		})
+		.dispatch(AccountId(42))
+		.unwrap();
+		reset_cache_stats();
+
+		for _ in 0..10 {
+			RuntimeCall::Currency(currency_module::Call::Transfer {
+				dest: AccountId(8),
+				amount: 5,
+			})
+			.dispatch(alice)
+			.unwrap();
+		}
+		let stats = cache_stats();
+		// only the very first read of bob's balance is a miss.
+		assert_eq!(stats.misses, 1);
+		assert!(stats.hits > 10);
+	}
+}
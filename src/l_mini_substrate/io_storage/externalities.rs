@@ -0,0 +1,140 @@
+//! Isolated storage for tests.
+//!
+//! All functions of `io_storage` operate on a thread-local storage, which is shared by everything
+//! running on the same thread. A [`TestExternalities`] instead holds a storage of its own, and
+//! installs it in place of the thread-local one only for the duration of
+//! [`TestExternalities::execute_with`].
+
+use super::{backend::StorageBackend, InMemoryBackend, Key, State, Value, STATE};
+use std::collections::BTreeMap;
+
+/// A self-contained storage, to run tests against a fresh and deterministic state.
+///
+/// ```
+/// # use pba_pre_course_assignment::l_mini_substrate::io_storage::{self, TestExternalities};
+/// let mut ext = TestExternalities::new(vec![(b"key".to_vec(), vec![1])]);
+///
+/// ext.execute_with(|| {
+///     assert_eq!(io_storage::get(b"key"), Some(vec![1]));
+///     io_storage::set(b"other", vec![2]);
+/// });
+///
+/// // nothing leaks out of `ext`..
+/// assert_eq!(io_storage::get(b"key"), None);
+/// assert_eq!(io_storage::get(b"other"), None);
+///
+/// // ..but it is all still there the next time.
+/// ext.execute_with(|| assert_eq!(io_storage::get(b"other"), Some(vec![2])));
+/// ```
+#[derive(Default)]
+pub struct TestExternalities {
+	state: State,
+}
+
+impl TestExternalities {
+	/// Create a new instance, with the given initial key-value pairs.
+	pub fn new(pairs: impl IntoIterator<Item = (Key, Value)>) -> Self {
+		Self::from_backend(Box::new(pairs.into_iter().collect::<InMemoryBackend>()))
+	}
+
+	/// Create a new instance, on top of the given `backend`.
+	pub fn from_backend(backend: Box<dyn StorageBackend>) -> Self {
+		Self {
+			state: State {
+				backend,
+				trie_stale: true,
+				..Default::default()
+			},
+		}
+	}
+
+	/// Execute `f` with this storage installed as the storage of the current thread.
+	///
+	/// The previous storage of the thread is restored afterwards, even if `f` panics.
+	pub fn execute_with<R>(&mut self, f: impl FnOnce() -> R) -> R {
+		/// Swaps the two storages back when dropped.
+		struct Guard<'a>(&'a mut State);
+		impl Drop for Guard<'_> {
+			fn drop(&mut self) {
+				STATE.with(|s| std::mem::swap(&mut *s.borrow_mut(), self.0));
+			}
+		}
+
+		STATE.with(|s| std::mem::swap(&mut *s.borrow_mut(), &mut self.state));
+		let _guard = Guard(&mut self.state);
+		f()
+	}
+
+	/// Consume `self`, returning all the key-value pairs in it, including the writes of any open
+	/// transactions.
+	pub fn into_pairs(mut self) -> BTreeMap<Key, Value> {
+		self.execute_with(|| super::iter_prefix(&[]).collect())
+	}
+}
+
+impl From<BTreeMap<Key, Value>> for TestExternalities {
+	fn from(pairs: BTreeMap<Key, Value>) -> Self {
+		Self::new(pairs)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::l_mini_substrate::io_storage::{self, get, set, storage_root};
+
+	#[test]
+	fn externalities_are_isolated() {
+		set(b"outside", vec![0]);
+
+		let mut a = TestExternalities::default();
+		let mut b = TestExternalities::default();
+		a.execute_with(|| {
+			assert_eq!(get(b"outside"), None);
+			set(b"key", vec![1]);
+		});
+		b.execute_with(|| {
+			assert_eq!(get(b"key"), None);
+			set(b"key", vec![2]);
+			// nesting works as well.
+			a.execute_with(|| assert_eq!(get(b"key"), Some(vec![1])));
+			assert_eq!(get(b"key"), Some(vec![2]));
+		});
+
+		assert_eq!(get(b"outside"), Some(vec![0]));
+		assert_eq!(
+			a.into_pairs(),
+			vec![(b"key".to_vec(), vec![1])].into_iter().collect()
+		);
+	}
+
+	#[test]
+	fn initial_pairs_are_part_of_the_root() {
+		let pairs = vec![(b"a".to_vec(), vec![1]), (b"b".to_vec(), vec![2])];
+		for (key, value) in pairs.clone() {
+			set(key, value);
+		}
+		let expected = storage_root();
+
+		let mut ext = TestExternalities::new(pairs);
+		assert_eq!(ext.execute_with(storage_root), expected);
+	}
+
+	#[test]
+	fn state_is_restored_after_panic() {
+		set(b"outside", vec![0]);
+		let mut ext = TestExternalities::default();
+		let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+			ext.execute_with(|| {
+				io_storage::start_transaction();
+				set(b"inside", vec![1]);
+				panic!("test panic");
+			})
+		}));
+
+		assert!(result.is_err());
+		assert_eq!(get(b"outside"), Some(vec![0]));
+		assert_eq!(io_storage::transaction_depth(), 0);
+		ext.execute_with(|| assert_eq!(io_storage::transaction_depth(), 1));
+	}
+}
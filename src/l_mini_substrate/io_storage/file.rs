@@ -0,0 +1,645 @@
+//! A persistent [`StorageBackend`], backed by a couple of files in a directory.
+//!
+//! The directory holds two files:
+//!
+//! 1. `snapshot`: the full key-value set, as of the last compaction.
+//! 2. `wal`: a write-ahead log of every write that has been committed since the last compaction.
+//!
+//! All data is also kept in memory, so reads never touch the disk. Writes are buffered until
+//! [`StorageBackend::commit`] is called, at which point they are appended to the log as one batch,
+//! closed by a commit marker, and synced to disk. Once the log grows beyond a threshold, it is
+//! compacted into a fresh snapshot. A commit that fails leaves the log as of the previous one, and
+//! keeps the writes pending for the next commit. If even the partially written batch can't be cut
+//! off the log, nothing is appended to it until that succeeds.
+//!
+//! Upon [`FileBackend::open`], the snapshot is loaded and the log is replayed on top of it, batch
+//! by batch. A batch that is not closed by a commit marker, or a record that is cut short or does
+//! not match its checksum (as it happens if the process crashes in the middle of a write), is
+//! discarded along with everything after it. The state is thus always recovered to the last
+//! successful commit.
+//!
+//! Each record in both files is framed as `len: u32 ++ checksum: u32 ++ payload`, where the
+//! payload is SCALE encoded.
+
+use super::{backend::StorageBackend, Key, Value};
+use parity_scale_codec::{Decode, Encode};
+use std::{
+	collections::BTreeMap,
+	fs::{self, File, OpenOptions},
+	io::{self, Write},
+	ops::Bound::{self, Unbounded},
+	path::{Path, PathBuf},
+};
+
+/// Name of the snapshot file within the directory.
+const SNAPSHOT_FILE: &str = "snapshot";
+/// Name of the write-ahead log file within the directory.
+const WAL_FILE: &str = "wal";
+/// The default size of the write-ahead log, in bytes, after which it is compacted.
+const DEFAULT_COMPACTION_THRESHOLD: u64 = 1024 * 1024;
+
+/// The file that the write-ahead log is appended to.
+///
+/// Only a trait such that tests can swap in a log that fails in the middle of a write.
+trait Log: Write + Send {
+	/// Truncate or extend the log to `len` bytes.
+	fn set_len(&self, len: u64) -> io::Result<()>;
+	/// Sync the content of the log to disk.
+	fn sync_data(&self) -> io::Result<()>;
+}
+
+impl Log for File {
+	fn set_len(&self, len: u64) -> io::Result<()> {
+		File::set_len(self, len)
+	}
+
+	fn sync_data(&self) -> io::Result<()> {
+		File::sync_data(self)
+	}
+}
+
+/// A single entry of the write-ahead log.
+#[derive(Encode, Decode, Debug, Clone, PartialEq, Eq)]
+enum WalRecord {
+	/// `key` was set to `value`.
+	Set(Key, Value),
+	/// `key` was removed.
+	Clear(Key),
+	/// All the records since the previous `Commit` are final.
+	Commit,
+}
+
+/// A persistent storage backend. See the module docs for more information.
+pub struct FileBackend {
+	/// The directory where all the files live.
+	dir: PathBuf,
+	/// The write-ahead log, opened for appending.
+	wal: Box<dyn Log>,
+	/// The size of the write-ahead log, up until the end of the last commit.
+	wal_len: u64,
+	/// Whether the log still holds a partial batch after `wal_len`, that a failed commit could not
+	/// cut off.
+	torn: bool,
+	/// The size of the write-ahead log after which it is compacted.
+	compaction_threshold: u64,
+	/// The latest data, including the uncommitted writes.
+	data: BTreeMap<Key, Value>,
+	/// The writes that have not been committed yet.
+	pending: Vec<WalRecord>,
+}
+
+impl FileBackend {
+	/// Open the backend stored in `dir`, creating it if it does not exist.
+	///
+	/// Recovers the state as of the last successful commit.
+	pub fn open(dir: impl AsRef<Path>) -> io::Result<Self> {
+		let dir = dir.as_ref().to_path_buf();
+		fs::create_dir_all(&dir)?;
+
+		let (data, wal_len) = load(&dir)?;
+
+		let wal = OpenOptions::new()
+			.create(true)
+			.append(true)
+			.open(dir.join(WAL_FILE))?;
+		// drop whatever follows the last commit, so that new batches are appended right after it.
+		wal.set_len(wal_len)?;
+		wal.sync_all()?;
+
+		Ok(Self {
+			dir,
+			wal: Box::new(wal),
+			wal_len,
+			torn: false,
+			compaction_threshold: DEFAULT_COMPACTION_THRESHOLD,
+			data,
+			pending: Vec::new(),
+		})
+	}
+
+	/// Compact the write-ahead log once it grows beyond `bytes`, rather than the default of 1 MiB.
+	pub fn with_compaction_threshold(mut self, bytes: u64) -> Self {
+		self.compaction_threshold = bytes;
+		self
+	}
+
+	/// The directory where this backend lives.
+	pub fn dir(&self) -> &Path {
+		&self.dir
+	}
+
+	/// Write all the data as of the last commit into a new snapshot, and empty the log.
+	///
+	/// The snapshot is first written to a temporary file and then renamed, so a crash at any point
+	/// leaves either the old or the new snapshot in place, and the log can always be replayed on
+	/// top of either of them.
+	pub fn compact(&mut self) -> io::Result<()> {
+		let committed = if self.pending.is_empty() {
+			self.data.clone()
+		} else {
+			// the pending writes are not part of the last commit, so go back to the files.
+			load(&self.dir)?.0
+		};
+
+		let pairs = committed.into_iter().collect::<Vec<_>>();
+		let tmp = self.dir.join(format!("{}.tmp", SNAPSHOT_FILE));
+		let mut file = File::create(&tmp)?;
+		file.write_all(&frame(&pairs.encode()))?;
+		file.sync_all()?;
+		fs::rename(&tmp, self.dir.join(SNAPSHOT_FILE))?;
+
+		self.wal.set_len(0)?;
+		self.wal.sync_data()?;
+		self.wal_len = 0;
+		self.torn = false;
+		Ok(())
+	}
+
+	/// Append all pending writes to the log as one batch, and sync it to disk.
+	///
+	/// The pending writes are only dropped once they are safely on disk. If anything fails, the
+	/// partially written batch is cut off the log, such that the next attempt follows the last
+	/// commit again. If that fails as well, the next attempt cuts it off before writing anything.
+	fn flush(&mut self) -> io::Result<()> {
+		if self.pending.is_empty() {
+			return Ok(());
+		}
+
+		if self.torn {
+			self.wal.set_len(self.wal_len)?;
+			self.torn = false;
+		}
+
+		let mut buffer = Vec::new();
+		for record in self
+			.pending
+			.iter()
+			.chain(std::iter::once(&WalRecord::Commit))
+		{
+			buffer.extend(frame(&record.encode()));
+		}
+		if let Err(error) = self
+			.wal
+			.write_all(&buffer)
+			.and_then(|_| self.wal.sync_data())
+		{
+			// the original error is what matters to the caller, even if the cut fails too.
+			self.torn = self.wal.set_len(self.wal_len).is_err();
+			return Err(error);
+		}
+		self.wal_len += buffer.len() as u64;
+		self.pending.clear();
+
+		if self.wal_len > self.compaction_threshold {
+			// the batch is already committed, and the log is replayed on top of whichever
+			// snapshot is in place, so a failed compaction is simply retried by the next commit.
+			let _ = self.compact();
+		}
+		Ok(())
+	}
+}
+
+impl StorageBackend for FileBackend {
+	fn get(&self, key: &[u8]) -> Option<Value> {
+		self.data.get(key).cloned()
+	}
+
+	fn get_with(&self, key: &[u8], f: &mut dyn FnMut(Option<&[u8]>)) {
+		f(self.data.get(key).map(Vec::as_slice))
+	}
+
+	fn set(&mut self, key: Key, value: Value) {
+		self.pending
+			.push(WalRecord::Set(key.clone(), value.clone()));
+		self.data.insert(key, value);
+	}
+
+	fn clear(&mut self, key: &[u8]) {
+		if self.data.remove(key).is_some() {
+			self.pending.push(WalRecord::Clear(key.to_vec()));
+		}
+	}
+
+	fn next_key(&self, from: Bound<&[u8]>) -> Option<Key> {
+		self.data
+			.range::<[u8], _>((from, Unbounded))
+			.next()
+			.map(|(k, _)| k.clone())
+	}
+
+	fn commit(&mut self) -> io::Result<()> {
+		self.flush()
+	}
+
+	fn iter(&self) -> Box<dyn Iterator<Item = (Key, Value)> + '_> {
+		Box::new(self.data.iter().map(|(k, v)| (k.clone(), v.clone())))
+	}
+}
+
+/// Load the data as of the last commit from the files in `dir`.
+///
+/// Returns the data, and the length of the valid prefix of the log.
+fn load(dir: &Path) -> io::Result<(BTreeMap<Key, Value>, u64)> {
+	let mut data = BTreeMap::new();
+	if let Some(raw) = read_if_exists(&dir.join(SNAPSHOT_FILE))? {
+		// the snapshot is only ever replaced atomically, so it must be intact.
+		let (payload, _) = read_frame(&raw).ok_or_else(|| corrupt("snapshot"))?;
+		let pairs = <Vec<(Key, Value)>>::decode(&mut &*payload).map_err(|_| corrupt("snapshot"))?;
+		data.extend(pairs);
+	}
+
+	let raw = read_if_exists(&dir.join(WAL_FILE))?.unwrap_or_default();
+	let wal_len = replay(&raw, &mut data) as u64;
+	Ok((data, wal_len))
+}
+
+/// Read the whole file at `path`, or `None` if it does not exist.
+///
+/// Any other error is returned, as the file might well hold committed data.
+fn read_if_exists(path: &Path) -> io::Result<Option<Vec<u8>>> {
+	match fs::read(path) {
+		Ok(raw) => Ok(Some(raw)),
+		Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(None),
+		Err(error) => Err(error),
+	}
+}
+
+/// Replay all the committed batches of the log in `raw` onto `data`.
+///
+/// Returns the length of the valid prefix of `raw`, i.e. up until the end of the last commit.
+fn replay(raw: &[u8], data: &mut BTreeMap<Key, Value>) -> usize {
+	let mut offset = 0;
+	let mut valid_len = 0;
+	let mut batch = Vec::new();
+
+	while let Some((payload, len)) = read_frame(&raw[offset..]) {
+		let Ok(record) = WalRecord::decode(&mut &*payload) else {
+			break;
+		};
+		offset += len;
+		match record {
+			WalRecord::Commit => {
+				for record in batch.drain(..) {
+					match record {
+						WalRecord::Set(key, value) => {
+							data.insert(key, value);
+						}
+						WalRecord::Clear(key) => {
+							data.remove(&key);
+						}
+						WalRecord::Commit => unreachable!("commits never end up in a batch; qed"),
+					}
+				}
+				valid_len = offset;
+			}
+			record => batch.push(record),
+		}
+	}
+
+	valid_len
+}
+
+/// Frame `payload` as `len ++ checksum ++ payload`.
+fn frame(payload: &[u8]) -> Vec<u8> {
+	let mut framed = Vec::with_capacity(payload.len() + 8);
+	framed.extend((payload.len() as u32).to_le_bytes());
+	framed.extend(checksum(payload).to_le_bytes());
+	framed.extend(payload);
+	framed
+}
+
+/// Read a single frame from the beginning of `raw`.
+///
+/// Returns the payload and the total length of the frame, or `None` if the frame is incomplete or
+/// its checksum does not match.
+fn read_frame(raw: &[u8]) -> Option<(&[u8], usize)> {
+	let header = raw.get(..8)?;
+	let len = u32::from_le_bytes(header[..4].try_into().expect("slice has 4 bytes; qed")) as usize;
+	let expected = u32::from_le_bytes(header[4..].try_into().expect("slice has 4 bytes; qed"));
+	let payload = raw.get(8..8usize.checked_add(len)?)?;
+	(checksum(payload) == expected).then_some((payload, 8 + len))
+}
+
+/// A simple FNV-1a checksum. Only meant to catch torn writes, not malicious tampering.
+fn checksum(data: &[u8]) -> u32 {
+	data.iter().fold(0x811c_9dc5u32, |hash, byte| {
+		(hash ^ *byte as u32).wrapping_mul(0x0100_0193)
+	})
+}
+
+/// The error returned when a file that cannot be partially written turns out to be corrupt.
+fn corrupt(what: &str) -> io::Error {
+	io::Error::new(io::ErrorKind::InvalidData, format!("corrupt {} file", what))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::l_mini_substrate::{
+		currency_module, io_storage,
+		runtime::{MyRuntime, RuntimeCall},
+		shared::{AccountId, Dispatchable, StorageMap},
+	};
+	use std::sync::atomic::{AtomicU32, Ordering};
+
+	/// A fresh directory, unique to each test.
+	fn test_dir(name: &str) -> PathBuf {
+		static COUNTER: AtomicU32 = AtomicU32::new(0);
+		let dir = std::env::temp_dir().join(format!(
+			"mini-substrate-{}-{}-{}",
+			name,
+			std::process::id(),
+			COUNTER.fetch_add(1, Ordering::Relaxed)
+		));
+		let _ = fs::remove_dir_all(&dir);
+		dir
+	}
+
+	#[test]
+	fn reopen_recovers_committed_state() {
+		let dir = test_dir("reopen");
+		{
+			let mut backend = FileBackend::open(&dir).unwrap();
+			backend.set(b"a".to_vec(), vec![1]);
+			backend.set(b"b".to_vec(), vec![2]);
+			backend.commit().unwrap();
+			backend.clear(b"a");
+			backend.commit().unwrap();
+			// never committed.
+			backend.set(b"c".to_vec(), vec![3]);
+		}
+
+		let backend = FileBackend::open(&dir).unwrap();
+		assert_eq!(
+			backend.iter().collect::<Vec<_>>(),
+			vec![(b"b".to_vec(), vec![2])]
+		);
+		fs::remove_dir_all(&dir).unwrap();
+	}
+
+	#[test]
+	fn truncated_log_recovers_last_commit() {
+		let dir = test_dir("truncated");
+		{
+			let mut backend = FileBackend::open(&dir).unwrap();
+			backend.set(b"a".to_vec(), vec![1]);
+			backend.commit().unwrap();
+			backend.set(b"a".to_vec(), vec![2]);
+			backend.set(b"b".to_vec(), vec![2]);
+			backend.commit().unwrap();
+		}
+
+		// chop the log in the middle of the last record, as if the process crashed mid-write.
+		let wal = dir.join(WAL_FILE);
+		let len = fs::metadata(&wal).unwrap().len();
+		OpenOptions::new()
+			.write(true)
+			.open(&wal)
+			.unwrap()
+			.set_len(len - 3)
+			.unwrap();
+
+		let mut backend = FileBackend::open(&dir).unwrap();
+		assert_eq!(backend.get(b"a"), Some(vec![1]));
+		assert_eq!(backend.get(b"b"), None);
+
+		// the torn tail is gone, so new commits are not affected by it.
+		backend.set(b"c".to_vec(), vec![3]);
+		backend.commit().unwrap();
+		drop(backend);
+
+		let backend = FileBackend::open(&dir).unwrap();
+		assert_eq!(
+			backend.iter().collect::<Vec<_>>(),
+			vec![(b"a".to_vec(), vec![1]), (b"c".to_vec(), vec![3])]
+		);
+		fs::remove_dir_all(&dir).unwrap();
+	}
+
+	/// A log that takes `budget` more bytes, and then fails as if the disk was full.
+	struct FailingLog {
+		file: File,
+		budget: usize,
+		/// Whether truncating the log fails as well.
+		stuck: bool,
+	}
+
+	impl FailingLog {
+		fn new(dir: &Path, budget: usize, stuck: bool) -> Box<Self> {
+			let file = OpenOptions::new()
+				.append(true)
+				.open(dir.join(WAL_FILE))
+				.unwrap();
+			Box::new(Self {
+				file,
+				budget,
+				stuck,
+			})
+		}
+	}
+
+	impl Write for FailingLog {
+		fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+			if self.budget == 0 {
+				return Err(io::Error::new(io::ErrorKind::Other, "disk full"));
+			}
+			let written = self.file.write(&buf[..buf.len().min(self.budget)])?;
+			self.budget -= written;
+			Ok(written)
+		}
+
+		fn flush(&mut self) -> io::Result<()> {
+			self.file.flush()
+		}
+	}
+
+	impl Log for FailingLog {
+		fn set_len(&self, len: u64) -> io::Result<()> {
+			if self.stuck {
+				return Err(io::Error::new(io::ErrorKind::PermissionDenied, "stuck"));
+			}
+			self.file.set_len(len)
+		}
+
+		fn sync_data(&self) -> io::Result<()> {
+			self.file.sync_data()
+		}
+	}
+
+	/// The current size of the log in `dir`.
+	fn wal_len(dir: &Path) -> u64 {
+		fs::metadata(dir.join(WAL_FILE)).unwrap().len()
+	}
+
+	#[test]
+	fn failed_commit_is_retried() {
+		let dir = test_dir("failed");
+		{
+			let mut backend = FileBackend::open(&dir).unwrap();
+			backend.set(b"a".to_vec(), vec![1]);
+			backend.commit().unwrap();
+			let committed = wal_len(&dir);
+
+			// the disk fills up in the middle of the batch, which is then cut off the log.
+			let wal = std::mem::replace(&mut backend.wal, FailingLog::new(&dir, 5, false));
+			backend.set(b"b".to_vec(), vec![2]);
+			assert!(backend.commit().is_err());
+			assert_eq!(wal_len(&dir), committed);
+
+			// the writes are still pending, and make it to disk once the log works again.
+			backend.wal = wal;
+			backend.set(b"c".to_vec(), vec![3]);
+			backend.commit().unwrap();
+		}
+
+		let backend = FileBackend::open(&dir).unwrap();
+		assert_eq!(backend.iter().count(), 3);
+		assert_eq!(backend.get(b"b"), Some(vec![2]));
+		fs::remove_dir_all(&dir).unwrap();
+	}
+
+	#[test]
+	fn torn_log_is_not_appended_to() {
+		let dir = test_dir("torn");
+		{
+			let mut backend = FileBackend::open(&dir).unwrap();
+			backend.set(b"a".to_vec(), vec![1]);
+			backend.commit().unwrap();
+			let committed = wal_len(&dir);
+
+			// neither the batch nor cutting it off works, and the error of the write is kept.
+			let wal = std::mem::replace(&mut backend.wal, FailingLog::new(&dir, 5, true));
+			backend.set(b"b".to_vec(), vec![2]);
+			assert_eq!(backend.commit().unwrap_err().kind(), io::ErrorKind::Other);
+			assert_eq!(wal_len(&dir), committed + 5);
+
+			// nothing is written after the torn bytes, as long as they can't be cut off.
+			backend.wal = FailingLog::new(&dir, usize::MAX, true);
+			backend.set(b"c".to_vec(), vec![3]);
+			assert_eq!(
+				backend.commit().unwrap_err().kind(),
+				io::ErrorKind::PermissionDenied
+			);
+			assert_eq!(wal_len(&dir), committed + 5);
+
+			// the torn bytes are cut off first, and the log ends right at the new commit.
+			backend.wal = wal;
+			backend.commit().unwrap();
+			assert_eq!(wal_len(&dir), backend.wal_len);
+		}
+
+		let backend = FileBackend::open(&dir).unwrap();
+		assert_eq!(backend.iter().count(), 3);
+		fs::remove_dir_all(&dir).unwrap();
+	}
+
+	#[test]
+	fn unreadable_files_are_not_ignored() {
+		let dir = test_dir("unreadable");
+		{
+			let mut backend = FileBackend::open(&dir).unwrap();
+			backend.set(b"a".to_vec(), vec![1]);
+			backend.commit().unwrap();
+			backend.compact().unwrap();
+			backend.set(b"b".to_vec(), vec![2]);
+			backend.commit().unwrap();
+		}
+
+		// a directory can't be read as a file, but is not missing either.
+		for (name, other) in [(WAL_FILE, SNAPSHOT_FILE), (SNAPSHOT_FILE, WAL_FILE)] {
+			let (path, moved) = (dir.join(name), dir.join("moved"));
+			let before = fs::read(dir.join(other)).unwrap();
+			fs::rename(&path, &moved).unwrap();
+			fs::create_dir(&path).unwrap();
+
+			assert!(FileBackend::open(&dir).is_err());
+			assert_eq!(fs::read(dir.join(other)).unwrap(), before);
+
+			fs::remove_dir(&path).unwrap();
+			fs::rename(&moved, &path).unwrap();
+		}
+
+		let backend = FileBackend::open(&dir).unwrap();
+		assert_eq!(backend.iter().count(), 2);
+		fs::remove_dir_all(&dir).unwrap();
+	}
+
+	#[test]
+	fn corrupt_record_is_discarded() {
+		let dir = test_dir("corrupt");
+		{
+			let mut backend = FileBackend::open(&dir).unwrap();
+			backend.set(b"a".to_vec(), vec![1]);
+			backend.commit().unwrap();
+			backend.set(b"a".to_vec(), vec![2]);
+			backend.commit().unwrap();
+		}
+
+		let wal = dir.join(WAL_FILE);
+		let mut raw = fs::read(&wal).unwrap();
+		let last = raw.len() - 1;
+		// flip a byte of the last commit marker.
+		raw[last] ^= 0xff;
+		fs::write(&wal, raw).unwrap();
+
+		let backend = FileBackend::open(&dir).unwrap();
+		assert_eq!(backend.get(b"a"), Some(vec![1]));
+		fs::remove_dir_all(&dir).unwrap();
+	}
+
+	#[test]
+	fn compaction_keeps_state() {
+		let dir = test_dir("compaction");
+		{
+			let mut backend = FileBackend::open(&dir)
+				.unwrap()
+				.with_compaction_threshold(64);
+			for i in 0..32u8 {
+				backend.set(vec![i], vec![i; 8]);
+				backend.commit().unwrap();
+			}
+			backend.clear(&[0]);
+			backend.commit().unwrap();
+			assert!(fs::metadata(dir.join(WAL_FILE)).unwrap().len() <= 64);
+			assert!(dir.join(SNAPSHOT_FILE).exists());
+
+			// uncommitted writes are not part of a manual compaction either.
+			backend.set(vec![100], vec![]);
+			backend.compact().unwrap();
+		}
+
+		let backend = FileBackend::open(&dir).unwrap();
+		assert_eq!(backend.iter().count(), 31);
+		assert_eq!(backend.get(&[0]), None);
+		assert_eq!(backend.get(&[31]), Some(vec![31; 8]));
+		assert_eq!(backend.get(&[100]), None);
+		fs::remove_dir_all(&dir).unwrap();
+	}
+
+	#[test]
+	fn runtime_survives_restart() {
+		let dir = test_dir("runtime");
+		let alice = AccountId(7);
+		{
+			let default = io_storage::set_backend(Box::new(FileBackend::open(&dir).unwrap()));
+			RuntimeCall::Currency(currency_module::Call::Mint {
+				dest: alice,
+				amount: 100,
+			})
+			.dispatch(AccountId(42))
+			.unwrap();
+			io_storage::commit().unwrap();
+			io_storage::set_backend(default);
+		}
+		assert!(!currency_module::BalancesMap::<MyRuntime>::exists(alice));
+
+		io_storage::set_backend(Box::new(FileBackend::open(&dir).unwrap()));
+		assert_eq!(
+			currency_module::BalancesMap::<MyRuntime>::get(alice)
+				.unwrap()
+				.free,
+			100
+		);
+		fs::remove_dir_all(&dir).unwrap();
+	}
+}
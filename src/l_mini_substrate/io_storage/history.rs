@@ -0,0 +1,260 @@
+//! Historical state, by block number.
+//!
+//! The storage keeps track of the current block number, starting at zero. [`finalize_block`] closes
+//! the current block and starts the next one. [`get_at`] then answers what the value of a key was
+//! at the end of any finalized block.
+//!
+//! To do so, the first time a key is written within a block, its previous value is recorded along
+//! with the block number. The value of a key at the end of block `n` is then the previous value
+//! recorded by the first block after `n` that wrote to it, or its current value if no such block
+//! exists.
+//!
+//! Keeping all of this forever is costly, so a pruning window can be set with
+//! [`set_pruning_window`]. Only the last `window` finalized blocks can then be queried, and
+//! anything needed only for older blocks is dropped.
+
+use super::{Key, Value, STATE};
+use std::collections::{BTreeMap, BTreeSet};
+
+/// The errors that can occur when querying historical state.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HistoryError {
+	/// The block is not finalized yet, so its final state is not known.
+	NotFinalized,
+	/// The block is older than the pruning window. The oldest block that can be queried is given.
+	Pruned { oldest: u32 },
+}
+
+/// The historical record of the storage.
+#[derive(Debug, Default)]
+pub(crate) struct History {
+	/// The current block number.
+	block: u32,
+	/// The number of finalized blocks to keep, `None` meaning all of them.
+	window: Option<u32>,
+	/// For each key, the value it had before each block that wrote to it.
+	changes: BTreeMap<Key, BTreeMap<u32, Option<Value>>>,
+	/// For each block, the keys that it wrote to. Only used for pruning.
+	changed_in: BTreeMap<u32, BTreeSet<Key>>,
+}
+
+impl History {
+	/// Whether `key` has already been written in the current block.
+	pub(crate) fn is_noted(&self, key: &[u8]) -> bool {
+		self.changes
+			.get(key)
+			.map_or(false, |changes| changes.contains_key(&self.block))
+	}
+
+	/// Note that `key` is written for the first time in the current block, while it still has
+	/// `previous` as value.
+	pub(crate) fn note(&mut self, key: &[u8], previous: Option<Value>) {
+		self.changes
+			.entry(key.to_vec())
+			.or_default()
+			.insert(self.block, previous);
+		self.changed_in
+			.entry(self.block)
+			.or_default()
+			.insert(key.to_vec());
+	}
+
+	/// The oldest block that can be queried.
+	fn oldest(&self) -> u32 {
+		self.window
+			.map_or(0, |window| self.block.saturating_sub(window))
+	}
+
+	/// Drop everything that is only needed to answer queries older than [`Self::oldest`].
+	fn prune(&mut self) {
+		let oldest = self.oldest();
+		// the record of block `n` is only used by queries for blocks before `n`.
+		let kept = self.changed_in.split_off(&(oldest + 1));
+		for (block, keys) in std::mem::replace(&mut self.changed_in, kept) {
+			for key in keys {
+				if let Some(changes) = self.changes.get_mut(&key) {
+					changes.remove(&block);
+					if changes.is_empty() {
+						self.changes.remove(&key);
+					}
+				}
+			}
+		}
+	}
+}
+
+/// The number of the current block, which is not finalized yet.
+pub fn block_number() -> u32 {
+	STATE.with(|s| s.borrow().history.block)
+}
+
+/// Finalize the current block, and start the next one.
+///
+/// Returns the number of the block that was finalized.
+pub fn finalize_block() -> u32 {
+	STATE.with(|s| {
+		let history = &mut s.borrow_mut().history;
+		let finalized = history.block;
+		history.block += 1;
+		history.prune();
+		finalized
+	})
+}
+
+/// Only keep the history of the last `window` finalized blocks, or all of them if `None`.
+pub fn set_pruning_window(window: Option<u32>) {
+	STATE.with(|s| {
+		let history = &mut s.borrow_mut().history;
+		history.window = window;
+		history.prune();
+	})
+}
+
+/// Get the value under `key` as of the end of the finalized block `block`.
+///
+/// ```
+/// # use pba_pre_course_assignment::l_mini_substrate::io_storage;
+/// io_storage::set(b"key", vec![1]);
+/// io_storage::finalize_block(); // block 0.
+/// io_storage::finalize_block(); // block 1, nothing changed.
+/// io_storage::set(b"key", vec![2]);
+/// io_storage::finalize_block(); // block 2.
+///
+/// assert_eq!(io_storage::get_at(b"key", 1), Ok(Some(vec![1])));
+/// assert_eq!(io_storage::get_at(b"key", 2), Ok(Some(vec![2])));
+/// assert_eq!(io_storage::get_at(b"key", 3), Err(io_storage::HistoryError::NotFinalized));
+/// ```
+pub fn get_at(key: &[u8], block: u32) -> Result<Option<Value>, HistoryError> {
+	STATE.with(|s| {
+		let state = s.borrow();
+		let history = &state.history;
+		if block >= history.block {
+			return Err(HistoryError::NotFinalized);
+		}
+		if block < history.oldest() {
+			return Err(HistoryError::Pruned {
+				oldest: history.oldest(),
+			});
+		}
+
+		let later_change = history
+			.changes
+			.get(key)
+			.and_then(|changes| changes.range(block + 1..).next())
+			.map(|(_, previous)| previous.clone());
+		Ok(later_change.unwrap_or_else(|| state.read(key)))
+	})
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::l_mini_substrate::{
+		currency_module::{self, BalancesMap},
+		io_storage::{self, clear, set, TestExternalities},
+		runtime::{MyRuntime, RuntimeCall},
+		shared::{AccountId, Dispatchable, StorageMap},
+	};
+	use parity_scale_codec::Decode;
+
+	#[test]
+	fn values_are_recorded_per_block() {
+		TestExternalities::default().execute_with(|| {
+			// block 0
+			set(b"a", vec![0]);
+			set(b"a", vec![1]);
+			assert_eq!(finalize_block(), 0);
+			// block 1
+			set(b"b", vec![1]);
+			finalize_block();
+			// block 2
+			clear(b"a");
+			finalize_block();
+			// block 3, not finalized.
+			set(b"a", vec![3]);
+
+			assert_eq!(block_number(), 3);
+			assert_eq!(get_at(b"a", 0), Ok(Some(vec![1])));
+			assert_eq!(get_at(b"a", 1), Ok(Some(vec![1])));
+			assert_eq!(get_at(b"a", 2), Ok(None));
+			assert_eq!(get_at(b"a", 3), Err(HistoryError::NotFinalized));
+			assert_eq!(get_at(b"b", 0), Ok(None));
+			assert_eq!(get_at(b"b", 2), Ok(Some(vec![1])));
+		})
+	}
+
+	#[test]
+	fn rolled_back_writes_are_not_part_of_history() {
+		TestExternalities::default().execute_with(|| {
+			set(b"a", vec![0]);
+			finalize_block();
+			let _ = io_storage::with_transaction(|| -> Result<(), ()> {
+				set(b"a", vec![1]);
+				Err(())
+			});
+			finalize_block();
+			set(b"a", vec![2]);
+
+			assert_eq!(get_at(b"a", 0), Ok(Some(vec![0])));
+			assert_eq!(get_at(b"a", 1), Ok(Some(vec![0])));
+		})
+	}
+
+	#[test]
+	fn pruning_drops_old_history() {
+		TestExternalities::default().execute_with(|| {
+			set_pruning_window(Some(3));
+			for i in 0..10u8 {
+				set(b"a", vec![i]);
+				finalize_block();
+			}
+
+			// blocks 7, 8 and 9 are kept.
+			assert_eq!(get_at(b"a", 6), Err(HistoryError::Pruned { oldest: 7 }));
+			assert_eq!(get_at(b"a", 7), Ok(Some(vec![7])));
+			assert_eq!(get_at(b"a", 9), Ok(Some(vec![9])));
+
+			STATE.with(|s| {
+				let history = &s.borrow().history;
+				assert_eq!(history.changes[&b"a".to_vec()].len(), 2);
+				assert_eq!(history.changed_in.len(), 2);
+			});
+
+			// growing the window does not bring anything back, but shrinking it prunes further.
+			set_pruning_window(Some(1));
+			assert_eq!(get_at(b"a", 8), Err(HistoryError::Pruned { oldest: 9 }));
+			assert_eq!(get_at(b"a", 9), Ok(Some(vec![9])));
+		})
+	}
+
+	#[test]
+	fn balance_at_past_block() {
+		TestExternalities::default().execute_with(|| {
+			let alice = AccountId(7);
+			RuntimeCall::Currency(currency_module::Call::Mint {
+				dest: alice,
+				amount: 1000,
+			})
+			.dispatch(AccountId(42))
+			.unwrap();
+			finalize_block();
+
+			// alice sends 5 to bob in every block.
+			for _ in 1..=130 {
+				RuntimeCall::Currency(currency_module::Call::Transfer {
+					dest: AccountId(8),
+					amount: 5,
+				})
+				.dispatch(alice)
+				.unwrap();
+				finalize_block();
+			}
+
+			let key = BalancesMap::<MyRuntime>::raw_storage_key(alice);
+			let raw = get_at(&key, 120).unwrap().unwrap();
+			let balance =
+				<BalancesMap<MyRuntime> as StorageMap>::Value::decode(&mut &*raw).unwrap();
+			assert_eq!(balance.free, 1000 - 120 * 5);
+		})
+	}
+}
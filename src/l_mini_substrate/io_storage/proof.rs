@@ -0,0 +1,244 @@
+//! Proofs of storage reads, that can be checked against a [`storage_root`](super::storage_root)
+//! without access to the storage itself.
+//!
+//! A [`StorageProof`] is simply a bag of trie nodes and values. The verifier indexes them by their
+//! hash, and walks down from the trusted root along the path of each key. Since every node commits
+//! to the hashes of its children and its value, anything that is reached this way is authentic.
+//!
+//! A key that does not exist is proven by the path that leads to where it would be: the walk ends
+//! at a node that has no child (or no value) for it.
+
+use super::{trie::EncodedNode, Key, Value, STATE};
+use crate::l_mini_substrate::{
+	hashing::{blake2_256, Hash},
+	io_storage::trie::nibbles,
+};
+use parity_scale_codec::{Decode, Encode};
+use std::collections::{BTreeMap, BTreeSet};
+
+/// A proof that some keys have (or do not have) some values, under some storage root.
+#[derive(Encode, Decode, Debug, Clone, Default, PartialEq, Eq)]
+pub struct StorageProof {
+	/// The encoded trie nodes, and the raw values, that are needed to verify the proof.
+	pub items: Vec<Vec<u8>>,
+}
+
+/// The errors that can occur when verifying a [`StorageProof`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ProofError {
+	/// A trie node with the given hash is needed, but is not part of the proof.
+	MissingNode(Hash),
+	/// A value with the given hash is needed, but is not part of the proof.
+	MissingValue(Hash),
+	/// A trie node within the proof could not be decoded.
+	InvalidNode(Hash),
+}
+
+/// Generate a proof for the current values of all `keys`, against the current
+/// [`storage_root`](super::storage_root).
+///
+/// ```
+/// # use pba_pre_course_assignment::l_mini_substrate::io_storage;
+/// io_storage::set(b"alice", vec![100]);
+/// io_storage::set(b"bob", vec![50]);
+///
+/// let root = io_storage::storage_root();
+/// let keys = vec![b"alice".to_vec(), b"charlie".to_vec()];
+/// let proof = io_storage::prove_read(&keys);
+///
+/// // no storage needed from here on.
+/// let values = io_storage::verify_read(root, &proof, &keys).unwrap();
+/// assert_eq!(values[&b"alice".to_vec()], Some(vec![100]));
+/// assert_eq!(values[&b"charlie".to_vec()], None);
+/// ```
+pub fn prove_read(keys: &[Key]) -> StorageProof {
+	STATE.with(|s| {
+		let mut state = s.borrow_mut();
+		state.sync_trie();
+
+		let mut items = BTreeSet::new();
+		for key in keys {
+			if state.trie.prove(key, &mut items).is_some() {
+				items.insert(
+					state
+						.read(key)
+						.expect("the trie is in sync with storage; qed"),
+				);
+			}
+		}
+		StorageProof {
+			items: items.into_iter().collect(),
+		}
+	})
+}
+
+/// Verify `proof` against the trusted `root`, returning the value of each of the `keys`.
+///
+/// This does not touch the storage in any way. Fails if the proof does not contain everything
+/// needed to determine the value of all `keys`.
+pub fn verify_read(
+	root: Hash,
+	proof: &StorageProof,
+	keys: &[Key],
+) -> Result<BTreeMap<Key, Option<Value>>, ProofError> {
+	let db = proof
+		.items
+		.iter()
+		.map(|item| (blake2_256(item), item.as_slice()))
+		.collect::<BTreeMap<_, _>>();
+
+	keys.iter()
+		.map(|key| verify_key(root, &db, key).map(|maybe_value| (key.clone(), maybe_value)))
+		.collect()
+}
+
+/// Walk from `root` to `key` using only the items in `db`.
+fn verify_key(
+	root: Hash,
+	db: &BTreeMap<Hash, &[u8]>,
+	key: &[u8],
+) -> Result<Option<Value>, ProofError> {
+	let path = nibbles(key);
+	let mut rest = path.as_slice();
+	let mut hash = root;
+
+	loop {
+		let raw = db.get(&hash).ok_or(ProofError::MissingNode(hash))?;
+		let node = EncodedNode::decode(&mut &raw[..]).map_err(|_| ProofError::InvalidNode(hash))?;
+		if !rest.starts_with(&node.partial) {
+			return Ok(None);
+		}
+		rest = &rest[node.partial.len()..];
+
+		let Some(index) = rest.first() else {
+			return match node.value {
+				Some(value_hash) => db
+					.get(&value_hash)
+					.map(|value| Some(value.to_vec()))
+					.ok_or(ProofError::MissingValue(value_hash)),
+				None => Ok(None),
+			};
+		};
+		match node.children.iter().find(|(i, _)| i == index) {
+			Some((_, child)) => hash = *child,
+			None => return Ok(None),
+		}
+		rest = &rest[1..];
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::l_mini_substrate::{
+		currency_module::BalancesMap,
+		io_storage::{self, storage_root},
+		runtime::{MyRuntime, RuntimeCall},
+		shared::{AccountId, Dispatchable, StorageMap},
+	};
+
+	fn populate() {
+		for i in 0..64u32 {
+			io_storage::set(i.to_be_bytes(), i.encode());
+		}
+	}
+
+	#[test]
+	fn proves_existence_and_non_existence() {
+		populate();
+		let root = storage_root();
+		let keys = vec![
+			3u32.to_be_bytes().to_vec(),
+			1000u32.to_be_bytes().to_vec(),
+			vec![0, 0],
+		];
+		let proof = prove_read(&keys);
+
+		let values = verify_read(root, &proof, &keys).unwrap();
+		assert_eq!(values[&keys[0]], Some(3u32.encode()));
+		assert_eq!(values[&keys[1]], None);
+		assert_eq!(values[&keys[2]], None);
+	}
+
+	#[test]
+	fn empty_storage_proof() {
+		let root = storage_root();
+		let keys = vec![b"anything".to_vec()];
+		let proof = prove_read(&keys);
+		assert_eq!(verify_read(root, &proof, &keys).unwrap()[&keys[0]], None);
+	}
+
+	#[test]
+	fn proof_against_wrong_root_fails() {
+		populate();
+		let keys = vec![3u32.to_be_bytes().to_vec()];
+		let proof = prove_read(&keys);
+
+		io_storage::set(3u32.to_be_bytes(), vec![]);
+		let new_root = storage_root();
+		assert!(matches!(
+			verify_read(new_root, &proof, &keys),
+			Err(ProofError::MissingNode(_))
+		));
+	}
+
+	#[test]
+	fn incomplete_or_tampered_proof_fails() {
+		populate();
+		let root = storage_root();
+		let keys = vec![3u32.to_be_bytes().to_vec()];
+		let proof = prove_read(&keys);
+
+		// dropping any single item makes the proof incomplete.
+		for i in 0..proof.items.len() {
+			let mut partial = proof.clone();
+			partial.items.remove(i);
+			assert!(verify_read(root, &partial, &keys).is_err());
+		}
+
+		// a value swapped for another one is simply not found.
+		let mut tampered = proof.clone();
+		let value = tampered
+			.items
+			.iter_mut()
+			.find(|item| **item == 3u32.encode())
+			.unwrap();
+		*value = 4u32.encode();
+		assert_eq!(
+			verify_read(root, &tampered, &keys),
+			Err(ProofError::MissingValue(blake2_256(&3u32.encode())))
+		);
+
+		// keys that the proof was not made for cannot be verified either.
+		let other = vec![40u32.to_be_bytes().to_vec()];
+		assert!(verify_read(root, &proof, &other).is_err());
+	}
+
+	#[test]
+	fn balance_can_be_verified_against_root() {
+		let alice = AccountId(7);
+		let bob = AccountId(8);
+		RuntimeCall::Currency(crate::l_mini_substrate::currency_module::Call::Mint {
+			dest: alice,
+			amount: 100,
+		})
+		.dispatch(AccountId(42))
+		.unwrap();
+
+		let root = storage_root();
+		let keys = vec![
+			BalancesMap::<MyRuntime>::raw_storage_key(alice),
+			BalancesMap::<MyRuntime>::raw_storage_key(bob),
+		];
+		let proof = prove_read(&keys);
+		// the wallet only holds `root`, `proof` and `keys` from here on.
+		let values = verify_read(root, &proof, &keys).unwrap();
+
+		let balance = <BalancesMap<MyRuntime> as StorageMap>::Value::decode(
+			&mut &values[&keys[0]].clone().unwrap()[..],
+		)
+		.unwrap();
+		assert_eq!(balance.free, 100);
+		assert_eq!(values[&keys[1]], None);
+	}
+}
@@ -0,0 +1,304 @@
+//! A backend that is shared between threads.
+//!
+//! The storage of `io_storage` is per thread. To make the same data visible on several threads, a
+//! [`SharedStorage`] is created once, and a clone of it is installed as the backend of every
+//! thread that needs it, via [`super::set_backend`]. All clones refer to the same data.
+//!
+//! The data sits behind a read-write lock, so any number of threads can read at the same time,
+//! while writes are exclusive. Each read or write only holds the lock for the duration of that
+//! single access. Transactions still work per thread: nothing is visible to other threads until it
+//! reaches the backend.
+//!
+//! Since a thread may observe writes from another thread in between two of its own reads, a
+//! consistent view of the data is instead obtained with [`SharedStorage::snapshot`]. The data is
+//! reference counted and copied on write, so a snapshot is cheap to take, and writers only pay for
+//! a copy while some snapshot is still alive.
+
+use super::{backend::StorageBackend, Key, Value};
+use std::{
+	collections::BTreeMap,
+	ops::Bound::{self, Unbounded},
+	sync::{Arc, RwLock, RwLockReadGuard},
+};
+
+/// The data shared by all clones of a [`SharedStorage`].
+#[derive(Debug, Default)]
+struct Inner {
+	data: Arc<BTreeMap<Key, Value>>,
+	/// Incremented on every write, by any handle.
+	version: u64,
+}
+
+/// A handle to a storage that can be shared between threads. See the module docs.
+///
+/// ```
+/// # use pba_pre_course_assignment::l_mini_substrate::io_storage::{self, SharedStorage};
+/// let shared = SharedStorage::default();
+/// io_storage::set_backend(Box::new(shared.clone()));
+/// io_storage::set(b"key", vec![1]);
+///
+/// std::thread::spawn(move || {
+///     io_storage::set_backend(Box::new(shared));
+///     assert_eq!(io_storage::get(b"key"), Some(vec![1]));
+/// })
+/// .join()
+/// .unwrap();
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct SharedStorage {
+	inner: Arc<RwLock<Inner>>,
+	/// The last version of the data that this handle knows about. See
+	/// [`StorageBackend::has_external_writes`].
+	seen: u64,
+}
+
+impl SharedStorage {
+	/// Create a new shared storage, with the given initial key-value pairs.
+	pub fn new(pairs: impl IntoIterator<Item = (Key, Value)>) -> Self {
+		let inner = Inner {
+			data: Arc::new(pairs.into_iter().collect()),
+			version: 0,
+		};
+		Self {
+			inner: Arc::new(RwLock::new(inner)),
+			seen: 0,
+		}
+	}
+
+	/// A consistent, read-only view of the data as it is now.
+	///
+	/// Writes made afterwards, through any handle, are not visible in the snapshot.
+	pub fn snapshot(&self) -> SharedSnapshot {
+		SharedSnapshot(self.read().data.clone())
+	}
+
+	fn read(&self) -> RwLockReadGuard<'_, Inner> {
+		self.inner
+			.read()
+			.expect("no access panics while holding the lock; qed")
+	}
+
+	fn write(&mut self, f: impl FnOnce(&mut BTreeMap<Key, Value>)) {
+		let mut inner = self
+			.inner
+			.write()
+			.expect("no access panics while holding the lock; qed");
+		f(Arc::make_mut(&mut inner.data));
+		// only keep up with the version if nobody else wrote since we last looked.
+		if inner.version == self.seen {
+			self.seen += 1;
+		}
+		inner.version += 1;
+	}
+}
+
+impl StorageBackend for SharedStorage {
+	fn get(&self, key: &[u8]) -> Option<Value> {
+		self.read().data.get(key).cloned()
+	}
+
+	fn get_with(&self, key: &[u8], f: &mut dyn FnMut(Option<&[u8]>)) {
+		f(self.read().data.get(key).map(Vec::as_slice))
+	}
+
+	fn set(&mut self, key: Key, value: Value) {
+		self.write(|data| {
+			data.insert(key, value);
+		})
+	}
+
+	fn clear(&mut self, key: &[u8]) {
+		self.write(|data| {
+			data.remove(key);
+		})
+	}
+
+	fn next_key(&self, from: Bound<&[u8]>) -> Option<Key> {
+		self.read()
+			.data
+			.range::<[u8], _>((from, Unbounded))
+			.next()
+			.map(|(k, _)| k.clone())
+	}
+
+	fn iter(&self) -> Box<dyn Iterator<Item = (Key, Value)> + '_> {
+		// iterate over a snapshot, rather than holding the lock for as long as the iterator lives.
+		let data = self.read().data.clone();
+		Box::new(
+			data.iter()
+				.map(|(k, v)| (k.clone(), v.clone()))
+				.collect::<Vec<_>>()
+				.into_iter(),
+		)
+	}
+
+	fn has_external_writes(&mut self) -> bool {
+		let version = self.read().version;
+		let changed = version != self.seen;
+		self.seen = version;
+		changed
+	}
+}
+
+/// A frozen view of a [`SharedStorage`], as returned by [`SharedStorage::snapshot`].
+///
+/// It can itself be installed as a backend, e.g. to serve queries against a fixed state. Writes to
+/// it are kept private to this snapshot.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SharedSnapshot(Arc<BTreeMap<Key, Value>>);
+
+impl StorageBackend for SharedSnapshot {
+	fn get(&self, key: &[u8]) -> Option<Value> {
+		self.0.get(key).cloned()
+	}
+
+	fn get_with(&self, key: &[u8], f: &mut dyn FnMut(Option<&[u8]>)) {
+		f(self.0.get(key).map(Vec::as_slice))
+	}
+
+	fn set(&mut self, key: Key, value: Value) {
+		Arc::make_mut(&mut self.0).insert(key, value);
+	}
+
+	fn clear(&mut self, key: &[u8]) {
+		Arc::make_mut(&mut self.0).remove(key);
+	}
+
+	fn next_key(&self, from: Bound<&[u8]>) -> Option<Key> {
+		self.0
+			.range::<[u8], _>((from, Unbounded))
+			.next()
+			.map(|(k, _)| k.clone())
+	}
+
+	fn iter(&self) -> Box<dyn Iterator<Item = (Key, Value)> + '_> {
+		Box::new(self.0.iter().map(|(k, v)| (k.clone(), v.clone())))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::l_mini_substrate::{
+		currency_module::{self, BalancesMap},
+		io_storage::{self, storage_root, TestExternalities},
+		runtime::{MyRuntime, RuntimeCall},
+		shared::{AccountId, Dispatchable, StorageMap},
+	};
+	use std::thread;
+
+	#[test]
+	fn writes_are_visible_on_other_threads() {
+		let shared = SharedStorage::new(vec![(b"a".to_vec(), vec![1])]);
+		let mut ext = TestExternalities::from_backend(Box::new(shared.clone()));
+
+		let reader = {
+			let shared = shared.clone();
+			thread::spawn(move || {
+				let mut ext = TestExternalities::from_backend(Box::new(shared));
+				ext.execute_with(|| {
+					assert_eq!(io_storage::get(b"a"), Some(vec![1]));
+					// wait for the writer below.
+					while io_storage::get(b"b").is_none() {
+						thread::yield_now();
+					}
+					storage_root()
+				})
+			})
+		};
+
+		let root = ext.execute_with(|| {
+			io_storage::set(b"b", vec![2]);
+			storage_root()
+		});
+		assert_eq!(reader.join().unwrap(), root);
+	}
+
+	#[test]
+	fn roots_follow_writes_from_other_handles() {
+		let shared = SharedStorage::default();
+		let mut a = TestExternalities::from_backend(Box::new(shared.clone()));
+		let mut b = TestExternalities::from_backend(Box::new(shared));
+
+		let empty = a.execute_with(storage_root);
+		let written = b.execute_with(|| {
+			io_storage::set(b"key", vec![1]);
+			storage_root()
+		});
+		assert_ne!(empty, written);
+		assert_eq!(a.execute_with(storage_root), written);
+	}
+
+	#[test]
+	fn transactions_stay_on_their_thread() {
+		let shared = SharedStorage::default();
+		let mut ext = TestExternalities::from_backend(Box::new(shared.clone()));
+		ext.execute_with(|| {
+			io_storage::start_transaction();
+			io_storage::set(b"a", vec![1]);
+		});
+
+		assert_eq!(shared.get(b"a"), None);
+		ext.execute_with(|| io_storage::commit_transaction().unwrap());
+		assert_eq!(shared.get(b"a"), Some(vec![1]));
+	}
+
+	#[test]
+	fn snapshots_are_consistent() {
+		let shared = SharedStorage::new(vec![(b"a".to_vec(), vec![100]), (b"b".to_vec(), vec![0])]);
+
+		// keep moving value from `a` to `b`, while always keeping the total at 100.
+		let writer = {
+			let mut shared = shared.clone();
+			thread::spawn(move || {
+				for i in 0..=100u8 {
+					shared.set(b"a".to_vec(), vec![100 - i]);
+					shared.set(b"b".to_vec(), vec![i]);
+				}
+			})
+		};
+
+		for _ in 0..100 {
+			let snapshot = shared.snapshot();
+			let a = snapshot.get(b"a").unwrap()[0];
+			thread::yield_now();
+			let b = snapshot.get(b"b").unwrap()[0];
+			// `a` may have been written, but not `b` yet.
+			assert!(a as u32 + b as u32 == 100 || a as u32 + b as u32 + 1 == 100);
+		}
+		writer.join().unwrap();
+
+		let snapshot = shared.snapshot();
+		let mut shared = shared;
+		shared.set(b"a".to_vec(), vec![1]);
+		assert_eq!(snapshot.get(b"a"), Some(vec![0]));
+	}
+
+	#[test]
+	fn runtime_can_be_queried_from_other_threads() {
+		let shared = SharedStorage::default();
+		let alice = AccountId(7);
+		let mut ext = TestExternalities::from_backend(Box::new(shared.clone()));
+		ext.execute_with(|| {
+			RuntimeCall::Currency(currency_module::Call::Mint {
+				dest: alice,
+				amount: 100,
+			})
+			.dispatch(AccountId(42))
+			.unwrap()
+		});
+
+		let readers = (0..4)
+			.map(|_| {
+				let shared = shared.clone();
+				thread::spawn(move || {
+					let mut ext = TestExternalities::from_backend(Box::new(shared));
+					ext.execute_with(|| BalancesMap::<MyRuntime>::get(alice).unwrap().free)
+				})
+			})
+			.collect::<Vec<_>>();
+		for reader in readers {
+			assert_eq!(reader.join().unwrap(), 100);
+		}
+	}
+}
@@ -0,0 +1,208 @@
+//! Exporting and importing the whole state, and comparing two states.
+//!
+//! A [`StateSnapshot`] is a plain copy of every key-value pair in storage, and is SCALE encodable,
+//! so it can be saved to a file and loaded back later, e.g. to reproduce a bug from a given state.
+//!
+//! [`diff`] lists the differences between two snapshots. Since both keys and values are raw bytes,
+//! [`StateDiff::render`] accepts a function that turns the entries it knows about into something
+//! human readable, such as `runtime::describe_storage_entry`.
+
+use super::{clear_prefix, iter_prefix, set, Key, Value};
+use parity_scale_codec::{Decode, Encode};
+use std::{collections::BTreeMap, fmt};
+
+/// All the key-value pairs in storage at some point in time.
+#[derive(Encode, Decode, Debug, Clone, Default, PartialEq, Eq)]
+pub struct StateSnapshot {
+	/// The data, ordered by key.
+	pub pairs: BTreeMap<Key, Value>,
+}
+
+/// Take a snapshot of the current state, including the writes of any open transactions.
+pub fn export_state() -> StateSnapshot {
+	StateSnapshot {
+		pairs: iter_prefix(&[]).collect(),
+	}
+}
+
+/// Replace the whole state with the content of `snapshot`.
+///
+/// This goes through the regular write path, so it can be done (and rolled back) within a
+/// transaction.
+pub fn import_state(snapshot: StateSnapshot) {
+	clear_prefix(&[], None);
+	for (key, value) in snapshot.pairs {
+		set(key, value);
+	}
+}
+
+/// The differences between two states, as returned by [`diff`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct StateDiff {
+	/// Keys that only exist in the new state, with their value.
+	pub added: Vec<(Key, Value)>,
+	/// Keys that only exist in the old state, with their value.
+	pub removed: Vec<(Key, Value)>,
+	/// Keys that exist in both, with their old and new value.
+	pub changed: Vec<(Key, Value, Value)>,
+}
+
+/// Compute what has changed going from state `a` to state `b`.
+pub fn diff(a: &StateSnapshot, b: &StateSnapshot) -> StateDiff {
+	let mut diff = StateDiff::default();
+	for (key, old) in a.pairs.iter() {
+		match b.pairs.get(key) {
+			None => diff.removed.push((key.clone(), old.clone())),
+			Some(new) if new != old => diff.changed.push((key.clone(), old.clone(), new.clone())),
+			Some(_) => {}
+		}
+	}
+	for (key, new) in b.pairs.iter() {
+		if !a.pairs.contains_key(key) {
+			diff.added.push((key.clone(), new.clone()));
+		}
+	}
+	diff
+}
+
+impl StateDiff {
+	/// Whether the two states were identical.
+	pub fn is_empty(&self) -> bool {
+		self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+	}
+
+	/// Render the diff, one line per key.
+	///
+	/// `describe` is given each raw key and value, and may return a human readable form of both.
+	/// Entries for which it returns `None` are rendered as hex.
+	///
+	/// Added keys are prefixed with `+`, removed ones with `-`, and changed ones with `~`.
+	pub fn render(&self, describe: impl Fn(&[u8], &[u8]) -> Option<(String, String)>) -> String {
+		let entry = |key: &[u8], value: &[u8]| {
+			describe(key, value).unwrap_or_else(|| (hex(key), hex(value)))
+		};
+
+		let mut lines = Vec::new();
+		for (key, value) in self.added.iter() {
+			let (key, value) = entry(key, value);
+			lines.push(format!("+ {}: {}", key, value));
+		}
+		for (key, value) in self.removed.iter() {
+			let (key, value) = entry(key, value);
+			lines.push(format!("- {}: {}", key, value));
+		}
+		for (key, old, new) in self.changed.iter() {
+			let (key_desc, old) = entry(key, old);
+			let (_, new) = entry(key, new);
+			lines.push(format!("~ {}: {} -> {}", key_desc, old, new));
+		}
+		lines.join("\n")
+	}
+}
+
+impl fmt::Display for StateDiff {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(f, "{}", self.render(|_, _| None))
+	}
+}
+
+/// `bytes` as a `0x` prefixed hex string.
+fn hex(bytes: &[u8]) -> String {
+	std::iter::once("0x".to_string())
+		.chain(bytes.iter().map(|b| format!("{:02x}", b)))
+		.collect()
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::l_mini_substrate::{
+		currency_module,
+		io_storage::{self, get},
+		runtime::{self, RuntimeCall},
+		shared::{AccountId, Dispatchable},
+	};
+
+	#[test]
+	fn export_import_roundtrip() {
+		set(b"a", vec![1]);
+		set(b"b", vec![2]);
+		let exported = export_state();
+		let encoded = exported.encode();
+
+		clear_prefix(&[], None);
+		set(b"c", vec![3]);
+
+		import_state(StateSnapshot::decode(&mut &*encoded).unwrap());
+		assert_eq!(export_state(), exported);
+		assert_eq!(get(b"c"), None);
+	}
+
+	#[test]
+	fn import_can_be_rolled_back() {
+		set(b"a", vec![1]);
+		let before = export_state();
+
+		let _ = io_storage::with_transaction(|| -> Result<(), ()> {
+			import_state(StateSnapshot::default());
+			assert_eq!(export_state(), StateSnapshot::default());
+			Err(())
+		});
+		assert_eq!(export_state(), before);
+	}
+
+	#[test]
+	fn diff_works() {
+		set(b"same", vec![0]);
+		set(b"changed", vec![1]);
+		set(b"removed", vec![2]);
+		let a = export_state();
+
+		set(b"changed", vec![3]);
+		io_storage::clear(b"removed");
+		set(b"added", vec![4]);
+		let b = export_state();
+
+		let d = diff(&a, &b);
+		assert_eq!(d.added, vec![(b"added".to_vec(), vec![4])]);
+		assert_eq!(d.removed, vec![(b"removed".to_vec(), vec![2])]);
+		assert_eq!(d.changed, vec![(b"changed".to_vec(), vec![1], vec![3])]);
+		assert!(diff(&a, &a).is_empty());
+
+		assert_eq!(
+			d.to_string(),
+			"+ 0x6164646564: 0x04\n- 0x72656d6f766564: 0x02\n~ 0x6368616e676564: 0x01 -> 0x03"
+		);
+	}
+
+	#[test]
+	fn diff_of_runtime_state_is_readable() {
+		let minter = AccountId(42);
+		let alice = AccountId(7);
+		let bob = AccountId(10);
+		RuntimeCall::Currency(currency_module::Call::Mint {
+			dest: alice,
+			amount: 100,
+		})
+		.dispatch(minter)
+		.unwrap();
+		let before = export_state();
+
+		RuntimeCall::Currency(currency_module::Call::Transfer {
+			dest: bob,
+			amount: 20,
+		})
+		.dispatch(alice)
+		.unwrap();
+		set(b"unknown", vec![1]);
+		let after = export_state();
+
+		assert_eq!(
+			diff(&before, &after).render(runtime::describe_storage_entry),
+			"+ BalancesMap[AccountId(10)]: AccountBalance { free: 20, reserved: 0 }\n\
+			 + 0x756e6b6e6f776e: 0x01\n\
+			 ~ BalancesMap[AccountId(7)]: AccountBalance { free: 100, reserved: 0 } -> \
+			 AccountBalance { free: 80, reserved: 0 }"
+		);
+	}
+}
@@ -0,0 +1,184 @@
+//! Metering of storage accesses.
+//!
+//! Every read and write that goes through the public functions of `io_storage` is counted, in two
+//! independent scopes:
+//!
+//! * The *dispatch* scope, which `runtime::RuntimeCall` resets at the beginning of each dispatch,
+//!   such that [`dispatch_stats`] reports the cost of the last dispatch.
+//! * The *block* scope, which is only reset by [`reset_block_stats`], such that [`block_stats`]
+//!   reports the total cost of everything since, e.g. all dispatches within a block.
+//!
+//! Writes are counted when they are made, regardless of whether their transaction is later
+//! committed or rolled back. Only the length of values is counted towards bytes, not that of keys.
+
+use super::STATE;
+use std::ops::Add;
+
+/// A snapshot of the storage access counters.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct StorageStats {
+	/// The number of reads, including iteration steps.
+	pub reads: u64,
+	/// The number of writes, including removals.
+	pub writes: u64,
+	/// The total length of all values read.
+	pub bytes_read: u64,
+	/// The total length of all values written.
+	pub bytes_written: u64,
+}
+
+impl Add for StorageStats {
+	type Output = Self;
+
+	fn add(self, other: Self) -> Self {
+		Self {
+			reads: self.reads.saturating_add(other.reads),
+			writes: self.writes.saturating_add(other.writes),
+			bytes_read: self.bytes_read.saturating_add(other.bytes_read),
+			bytes_written: self.bytes_written.saturating_add(other.bytes_written),
+		}
+	}
+}
+
+/// The counters of both scopes.
+#[derive(Debug, Default, Clone, Copy)]
+pub(crate) struct Meter {
+	dispatch: StorageStats,
+	block: StorageStats,
+}
+
+impl Meter {
+	/// Record a read of a value of `len` bytes.
+	pub(crate) fn read(&mut self, len: usize) {
+		self.record(StorageStats {
+			reads: 1,
+			bytes_read: len as u64,
+			..Default::default()
+		})
+	}
+
+	/// Record a write of a value of `len` bytes.
+	pub(crate) fn write(&mut self, len: usize) {
+		self.record(StorageStats {
+			writes: 1,
+			bytes_written: len as u64,
+			..Default::default()
+		})
+	}
+
+	fn record(&mut self, stats: StorageStats) {
+		self.dispatch = self.dispatch + stats;
+		self.block = self.block + stats;
+	}
+}
+
+/// The storage accesses since the last [`reset_dispatch_stats`].
+pub fn dispatch_stats() -> StorageStats {
+	STATE.with(|s| s.borrow().meter.dispatch)
+}
+
+/// Reset the dispatch counters, returning their last value.
+pub fn reset_dispatch_stats() -> StorageStats {
+	STATE.with(|s| std::mem::take(&mut s.borrow_mut().meter.dispatch))
+}
+
+/// The storage accesses since the last [`reset_block_stats`].
+pub fn block_stats() -> StorageStats {
+	STATE.with(|s| s.borrow().meter.block)
+}
+
+/// Reset the block counters, returning their last value.
+pub fn reset_block_stats() -> StorageStats {
+	STATE.with(|s| std::mem::take(&mut s.borrow_mut().meter.block))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::l_mini_substrate::{
+		currency_module,
+		io_storage::{self, TestExternalities},
+		runtime::RuntimeCall,
+		shared::{AccountId, Dispatchable},
+		staking_module,
+	};
+
+	#[test]
+	fn reads_and_writes_are_counted() {
+		TestExternalities::default().execute_with(|| {
+			io_storage::set(b"a", vec![0; 10]);
+			io_storage::set(b"b", vec![0; 5]);
+			io_storage::clear(b"b");
+			assert_eq!(io_storage::get(b"a"), Some(vec![0; 10]));
+			assert_eq!(io_storage::get(b"b"), None);
+			assert_eq!(io_storage::iter_prefix(b"").count(), 1);
+
+			let expected = StorageStats {
+				reads: 3,
+				writes: 3,
+				bytes_read: 20,
+				bytes_written: 15,
+			};
+			assert_eq!(dispatch_stats(), expected);
+			assert_eq!(block_stats(), expected);
+
+			assert_eq!(reset_dispatch_stats(), expected);
+			assert_eq!(dispatch_stats(), StorageStats::default());
+			assert_eq!(block_stats(), expected);
+			assert_eq!(reset_block_stats(), expected);
+			assert_eq!(block_stats(), StorageStats::default());
+		})
+	}
+
+	#[test]
+	fn rolled_back_writes_are_still_counted() {
+		TestExternalities::default().execute_with(|| {
+			let _ = io_storage::with_transaction(|| -> Result<(), ()> {
+				io_storage::set(b"a", vec![0; 4]);
+				Err(())
+			});
+			assert_eq!(dispatch_stats().writes, 1);
+			assert_eq!(dispatch_stats().bytes_written, 4);
+		})
+	}
+
+	#[test]
+	fn transfer_and_bond_are_metered() {
+		TestExternalities::default().execute_with(|| {
+			let alice = AccountId(7);
+			// an `AccountBalance` with two `u64`s.
+			let balance_len = 16;
+
+			RuntimeCall::Currency(currency_module::Call::Mint {
+				dest: alice,
+				amount: 100,
+			})
+			.dispatch(AccountId(42))
+			.unwrap();
+			reset_block_stats();
+
+			let bob = AccountId(8);
+			RuntimeCall::Currency(currency_module::Call::Transfer {
+				dest: bob,
+				amount: 10,
+			})
+			.dispatch(alice)
+			.unwrap();
+			let transfer = dispatch_stats();
+			assert_eq!(transfer.writes, 2);
+			assert_eq!(transfer.bytes_written, 2 * balance_len);
+			assert!(transfer.reads > 0);
+
+			RuntimeCall::Staking(staking_module::Call::Bond { amount: 10 })
+				.dispatch(alice)
+				.unwrap();
+			let bond = dispatch_stats();
+			assert_eq!(bond.writes, 1);
+			assert_eq!(bond.bytes_written, balance_len);
+			assert!(bond.reads > 0);
+
+			// the block saw both of them.
+			assert_eq!(block_stats(), transfer + bond);
+		})
+	}
+}
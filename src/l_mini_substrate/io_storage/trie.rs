@@ -0,0 +1,321 @@
+//! A Merkle Patricia trie over the keys of `io_storage`, used to compute the
+//! [`super::storage_root`].
+//!
+//! Keys are split into nibbles (half-bytes), making each node a radix-16 branch. Nodes with a
+//! single child and no value never exist, as their path is instead folded into their child's
+//! `partial` key.
+//!
+//! The trie does not hold the values themselves, only their [`blake2_256`] hash. Each node caches
+//! its own hash, which is invalidated along the path of every insertion or removal. Computing the
+//! root thus only rehashes the nodes that have changed since the last time.
+//!
+//! The hash of a node is the [`blake2_256`] of its [`EncodedNode`]. The root of an empty trie is
+//! the hash of an empty node.
+
+use crate::l_mini_substrate::hashing::{blake2_256, Hash};
+use parity_scale_codec::{Decode, Encode};
+use std::collections::BTreeSet;
+
+/// The representation of a node that is hashed, and shared in proofs.
+#[derive(Encode, Decode, Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) struct EncodedNode {
+	/// The nibbles of the key that lead from the parent to this node.
+	pub(crate) partial: Vec<u8>,
+	/// The hash of the value stored at this node, if any.
+	pub(crate) value: Option<Hash>,
+	/// The index and hash of all children of this node, in ascending order.
+	pub(crate) children: Vec<(u8, Hash)>,
+}
+
+/// A node of the in-memory trie.
+#[derive(Debug, Default)]
+struct Node {
+	/// The nibbles of the key that lead from the parent to this node.
+	partial: Vec<u8>,
+	/// The hash of the value stored at this node, if any.
+	value: Option<Hash>,
+	/// One slot per nibble.
+	children: [Option<Box<Node>>; 16],
+	/// The hash of this node, if it is still up to date.
+	hash: Option<Hash>,
+}
+
+impl Node {
+	fn new(partial: Vec<u8>) -> Self {
+		Self {
+			partial,
+			..Default::default()
+		}
+	}
+
+	/// Get the hash of this node, recomputing it (and its children's) only where necessary.
+	fn hash(&mut self) -> Hash {
+		if let Some(hash) = self.hash {
+			return hash;
+		}
+		let hash = blake2_256(&self.encode_node().encode());
+		self.hash = Some(hash);
+		hash
+	}
+
+	/// The encoded form of this node.
+	fn encode_node(&mut self) -> EncodedNode {
+		let children = self
+			.children
+			.iter_mut()
+			.enumerate()
+			.filter_map(|(i, child)| child.as_mut().map(|child| (i as u8, child.hash())))
+			.collect();
+		EncodedNode {
+			partial: self.partial.clone(),
+			value: self.value,
+			children,
+		}
+	}
+
+	/// Number of children of this node.
+	fn child_count(&self) -> usize {
+		self.children.iter().filter(|c| c.is_some()).count()
+	}
+}
+
+/// The in-memory trie. See the module docs.
+#[derive(Debug, Default)]
+pub(crate) struct Trie {
+	root: Option<Box<Node>>,
+}
+
+impl Trie {
+	/// Set the value under `key`, which has the given `value_hash`.
+	pub(crate) fn insert(&mut self, key: &[u8], value_hash: Hash) {
+		insert(&mut self.root, &nibbles(key), value_hash)
+	}
+
+	/// Remove the value under `key`, if any.
+	pub(crate) fn remove(&mut self, key: &[u8]) {
+		remove(&mut self.root, &nibbles(key));
+	}
+
+	/// Remove everything.
+	pub(crate) fn clear(&mut self) {
+		self.root = None;
+	}
+
+	/// Add the encoding of every node on the path to `key` to `proof`.
+	///
+	/// Returns the hash of the value under `key`, if any.
+	pub(crate) fn prove(&mut self, key: &[u8], proof: &mut BTreeSet<Vec<u8>>) -> Option<Hash> {
+		let path = nibbles(key);
+		let mut rest = path.as_slice();
+		let Some(mut node) = self.root.as_deref_mut() else {
+			proof.insert(EncodedNode::default().encode());
+			return None;
+		};
+
+		loop {
+			proof.insert(node.encode_node().encode());
+			if !rest.starts_with(&node.partial) {
+				return None;
+			}
+			rest = &rest[node.partial.len()..];
+			let Some(index) = rest.first() else {
+				return node.value;
+			};
+			node = node.children[*index as usize].as_deref_mut()?;
+			rest = &rest[1..];
+		}
+	}
+
+	/// The Merkle root of the trie.
+	pub(crate) fn root(&mut self) -> Hash {
+		match self.root {
+			Some(ref mut root) => root.hash(),
+			None => empty_root(),
+		}
+	}
+}
+
+/// The root of an empty trie.
+pub(crate) fn empty_root() -> Hash {
+	blake2_256(&EncodedNode::default().encode())
+}
+
+/// Split `key` into its nibbles, most significant first.
+pub(crate) fn nibbles(key: &[u8]) -> Vec<u8> {
+	key.iter()
+		.flat_map(|byte| [byte >> 4, byte & 0x0f])
+		.collect()
+}
+
+fn insert(slot: &mut Option<Box<Node>>, path: &[u8], value_hash: Hash) {
+	let node = match slot {
+		Some(node) => node,
+		None => {
+			let mut leaf = Node::new(path.to_vec());
+			leaf.value = Some(value_hash);
+			*slot = Some(Box::new(leaf));
+			return;
+		}
+	};
+
+	node.hash = None;
+	let common = node
+		.partial
+		.iter()
+		.zip(path)
+		.take_while(|(a, b)| a == b)
+		.count();
+	if common < node.partial.len() {
+		// `path` diverges in the middle of this node: put a new branch above it.
+		let mut old = slot.take().expect("matched as `Some` above; qed");
+		let branch_partial = old.partial[..common].to_vec();
+		let index = old.partial[common] as usize;
+		old.partial.drain(..=common);
+		let mut branch = Node::new(branch_partial);
+		branch.children[index] = Some(old);
+		*slot = Some(Box::new(branch));
+		return insert(slot, path, value_hash);
+	}
+
+	match path.get(common) {
+		None => node.value = Some(value_hash),
+		Some(index) => insert(
+			&mut node.children[*index as usize],
+			&path[common + 1..],
+			value_hash,
+		),
+	}
+}
+
+/// Remove `path` from the sub-trie in `slot`. Returns `true` if anything was removed.
+fn remove(slot: &mut Option<Box<Node>>, path: &[u8]) -> bool {
+	let Some(node) = slot else { return false };
+	if !path.starts_with(&node.partial) {
+		return false;
+	}
+
+	let removed = match path.get(node.partial.len()) {
+		None => node.value.take().is_some(),
+		Some(index) => remove(
+			&mut node.children[*index as usize],
+			&path[node.partial.len() + 1..],
+		),
+	};
+	if !removed {
+		return false;
+	}
+
+	node.hash = None;
+	if node.value.is_none() {
+		match node.child_count() {
+			0 => *slot = None,
+			1 => {
+				// fold this node into its only child.
+				let (index, child) = node
+					.children
+					.iter_mut()
+					.enumerate()
+					.find_map(|(i, c)| c.take().map(|c| (i, c)))
+					.expect("exactly one child exists; qed");
+				let mut child = child;
+				let mut partial = std::mem::take(&mut node.partial);
+				partial.push(index as u8);
+				partial.append(&mut child.partial);
+				child.partial = partial;
+				child.hash = None;
+				*slot = Some(child);
+			}
+			_ => {}
+		}
+	}
+	true
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	/// Build a trie from scratch, out of `pairs`.
+	fn build(pairs: &[(&[u8], &[u8])]) -> Trie {
+		let mut trie = Trie::default();
+		for (key, value) in pairs {
+			trie.insert(key, blake2_256(value));
+		}
+		trie
+	}
+
+	#[test]
+	fn empty_trie_root() {
+		assert_eq!(Trie::default().root(), empty_root());
+		let mut trie = build(&[(b"a", b"1")]);
+		trie.remove(b"a");
+		assert_eq!(trie.root(), empty_root());
+	}
+
+	#[test]
+	fn root_is_independent_of_insertion_order() {
+		let pairs: [(&[u8], &[u8]); 5] = [
+			(b"abc", b"1"),
+			(b"abd", b"2"),
+			(b"ab", b"3"),
+			(b"b", b"4"),
+			(b"", b"5"),
+		];
+		let mut forward = build(&pairs);
+		let mut reversed = pairs;
+		reversed.reverse();
+		let mut backward = build(&reversed);
+		assert_eq!(forward.root(), backward.root());
+	}
+
+	#[test]
+	fn root_changes_with_values_and_keys() {
+		let mut trie = build(&[(b"a", b"1"), (b"b", b"2")]);
+		let root = trie.root();
+
+		trie.insert(b"a", blake2_256(b"changed"));
+		let changed = trie.root();
+		assert_ne!(root, changed);
+
+		trie.insert(b"a", blake2_256(b"1"));
+		assert_eq!(trie.root(), root);
+
+		trie.insert(b"c", blake2_256(b"3"));
+		assert_ne!(trie.root(), root);
+	}
+
+	#[test]
+	fn removal_restores_canonical_shape() {
+		let mut trie = build(&[(b"abc", b"1"), (b"abd", b"2"), (b"x", b"3")]);
+		trie.remove(b"abd");
+		trie.remove(b"x");
+		// removing a key that does not exist is a noop.
+		trie.remove(b"ab");
+		trie.remove(b"zzz");
+
+		assert_eq!(trie.root(), build(&[(b"abc", b"1")]).root());
+	}
+
+	#[test]
+	fn incremental_root_matches_full_rebuild() {
+		let mut trie = Trie::default();
+		let mut reference = std::collections::BTreeMap::new();
+		// a simple deterministic sequence of inserts and removals.
+		for i in 0..500u32 {
+			let key = (i.wrapping_mul(2654435761) % 97).to_le_bytes()[..2].to_vec();
+			if i % 3 == 0 {
+				trie.remove(&key);
+				reference.remove(&key);
+			} else {
+				trie.insert(&key, blake2_256(&i.encode()));
+				reference.insert(key, blake2_256(&i.encode()));
+			}
+
+			if i % 50 == 0 {
+				let mut rebuilt = Trie::default();
+				reference.iter().for_each(|(k, v)| rebuilt.insert(k, *v));
+				assert_eq!(trie.root(), rebuilt.root());
+			}
+		}
+	}
+}
@@ -0,0 +1,163 @@
+//! The [`storage!`](crate::storage) macro, which declares storage items without any boilerplate.
+
+/// Declare storage values and maps, one per line.
+///
+/// Each declaration generates a struct called `Name`, optionally generic over a single `T` with a
+/// bound, e.g. a module's `Config`, along with its [`StorageValue`] or [`StorageMap`]
+/// implementation:
+///
+/// * `$vis Name = value(module, Value, QueryKind);`
+/// * `$vis Name = map(module, Key => Value, Hasher, QueryKind);`
+///
+/// where `module` is a `&'static str` expression such as `T::MODULE_ID`. The storage prefix is
+/// always the name of the struct itself, such that the keys follow the specification of the
+/// [module docs](super), and can't be gotten wrong by hand:
+///
+/// * the key of a value is `concat(twox_128(module), twox_128(b"Name"))`.
+/// * the key of `k` in a map is
+///   `concat(twox_128(module), twox_128(b"Name"), Hasher::hash(encode(k)))`.
+///
+/// Doc comments and other attributes are kept on the generated struct.
+///
+/// ```
+/// # use pba_pre_course_assignment::{
+/// #     l_mini_substrate::{
+/// #         hashing::twox_128,
+/// #         shared::{
+/// #             OptionQuery, StorageHasher, StorageMap, StorageValue, Twox64Concat, ValueQuery,
+/// #         },
+/// #     },
+/// #     storage,
+/// # };
+/// # use parity_scale_codec::Encode;
+/// pub trait Config {
+///     const MODULE_ID: &'static str;
+/// }
+///
+/// storage! {
+///     /// The number of nominators.
+///     pub NominatorCount = value("Staking", u32, ValueQuery);
+///     /// The targets of each nominator.
+///     pub Nominations<T: Config> =
+///         map(T::MODULE_ID, u32 => Vec<u32>, Twox64Concat, OptionQuery);
+/// }
+///
+/// struct Runtime;
+/// impl Config for Runtime {
+///     const MODULE_ID: &'static str = "Staking";
+/// }
+///
+/// assert_eq!(
+///     NominatorCount::raw_storage_key(),
+///     [twox_128(b"Staking"), twox_128(b"NominatorCount")].concat()
+/// );
+/// assert_eq!(
+///     Nominations::<Runtime>::raw_storage_key(7),
+///     [&twox_128(b"Staking")[..], &twox_128(b"Nominations"), &Twox64Concat::hash(&7u32.encode())]
+///         .concat()
+/// );
+/// ```
+///
+/// [`StorageValue`]: crate::l_mini_substrate::shared::StorageValue
+/// [`StorageMap`]: crate::l_mini_substrate::shared::StorageMap
+#[macro_export]
+macro_rules! storage {
+	() => {};
+
+	(
+		$(#[$attr:meta])*
+		$vis:vis $name:ident $(<$param:ident: $bound:path>)? =
+			value($module:expr, $value:ty, $query:ty $(,)?);
+		$($rest:tt)*
+	) => {
+		$(#[$attr])*
+		$vis struct $name$(<$param: $bound>)?(::std::marker::PhantomData<($($param,)?)>);
+
+		impl$(<$param: $bound>)? $crate::l_mini_substrate::shared::StorageValue
+			for $name$(<$param>)?
+		{
+			type Value = $value;
+			type QueryKind = $query;
+			fn module_prefix() -> &'static str {
+				$module
+			}
+			fn storage_prefix() -> &'static str {
+				stringify!($name)
+			}
+		}
+
+		$crate::storage!($($rest)*);
+	};
+
+	(
+		$(#[$attr:meta])*
+		$vis:vis $name:ident $(<$param:ident: $bound:path>)? =
+			map($module:expr, $key:ty => $value:ty, $hasher:ty, $query:ty $(,)?);
+		$($rest:tt)*
+	) => {
+		$(#[$attr])*
+		$vis struct $name$(<$param: $bound>)?(::std::marker::PhantomData<($($param,)?)>);
+
+		impl$(<$param: $bound>)? $crate::l_mini_substrate::shared::StorageMap
+			for $name$(<$param>)?
+		{
+			type Key = $key;
+			type Value = $value;
+			type Hasher = $hasher;
+			type QueryKind = $query;
+			fn module_prefix() -> &'static str {
+				$module
+			}
+			fn storage_prefix() -> &'static str {
+				stringify!($name)
+			}
+		}
+
+		$crate::storage!($($rest)*);
+	};
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::l_mini_substrate::{
+		io_storage::TestExternalities,
+		shared::{
+			storage_prefix, Blake2_128Concat, GetDefault, Identity, OptionQuery, StorageMap,
+			StorageValue, ValueQuery,
+		},
+	};
+
+	crate::storage! {
+		Counter = value("Test", u32, ValueQuery);
+		Names = map("Test", u32 => String, Identity, OptionQuery);
+		Scores = map("Other", u32 => u64, Blake2_128Concat, ValueQuery<GetDefault>);
+	}
+
+	#[test]
+	fn items_are_named_after_their_struct() {
+		assert_eq!(
+			Counter::raw_storage_key(),
+			storage_prefix("Test", "Counter")
+		);
+		assert_eq!(
+			(Names::module_prefix(), Names::storage_prefix()),
+			("Test", "Names")
+		);
+		assert_eq!(
+			Names::raw_storage_key(1),
+			[storage_prefix("Test", "Names"), vec![1, 0, 0, 0]].concat()
+		);
+		assert_eq!(Scores::prefix(), storage_prefix("Other", "Scores"));
+	}
+
+	#[test]
+	fn generated_items_work() {
+		TestExternalities::default().execute_with(|| {
+			Counter::mutate(|count| *count += 1);
+			assert_eq!(Counter::get(), 1);
+			Names::set(1, "Alice".into());
+			assert_eq!(Names::get(1), Some("Alice".into()));
+			assert_eq!(Scores::get(1), 0);
+		});
+	}
+}
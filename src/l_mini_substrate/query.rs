@@ -0,0 +1,142 @@
+//! How storage items present values that are missing from the storage.
+//!
+//! Every [`shared::StorageValue`](super::shared::StorageValue) and
+//! [`shared::StorageMap`](super::shared::StorageMap) declares a query kind, which decides what
+//! their `get` returns:
+//!
+//! * [`OptionQuery`]: `None` if the value is missing, `Some(_)` otherwise.
+//! * [`ValueQuery`]: the value itself, or some default if it is missing, as given by a
+//!   [`Get`] type, [`GetDefault`] unless stated otherwise.
+//! * [`ResultQuery`]: `Ok(_)` with the value, or some error if it is missing, again as given by a
+//!   [`Get`] type.
+//!
+//! The same goes the other way around: whatever ends up as the default of a [`ValueQuery`] item,
+//! or as an error of a [`ResultQuery`] item, is removed from the storage rather than stored.
+
+use crate::get::Get;
+use std::marker::PhantomData;
+
+/// The way a storage item presents its possibly missing values. See the module docs.
+pub trait QueryKindTrait<V> {
+	/// What the storage item returns when queried.
+	type Query;
+
+	/// The query for what is in the storage, `None` if nothing is.
+	fn from_optional_value(value: Option<V>) -> Self::Query;
+
+	/// What should be in the storage for `query`, `None` if nothing should be.
+	fn into_optional_value(query: Self::Query) -> Option<V>;
+}
+
+/// Query missing values as `None`.
+pub struct OptionQuery;
+
+impl<V> QueryKindTrait<V> for OptionQuery {
+	type Query = Option<V>;
+
+	fn from_optional_value(value: Option<V>) -> Self::Query {
+		value
+	}
+
+	fn into_optional_value(query: Self::Query) -> Option<V> {
+		query
+	}
+}
+
+/// Query missing values as `OnEmpty::get()`. Values equal to it are not stored.
+pub struct ValueQuery<OnEmpty = GetDefault>(PhantomData<OnEmpty>);
+
+impl<V: PartialEq, OnEmpty: Get<V>> QueryKindTrait<V> for ValueQuery<OnEmpty> {
+	type Query = V;
+
+	fn from_optional_value(value: Option<V>) -> Self::Query {
+		value.unwrap_or_else(OnEmpty::get)
+	}
+
+	fn into_optional_value(query: Self::Query) -> Option<V> {
+		(query != OnEmpty::get()).then_some(query)
+	}
+}
+
+/// Query missing values as `Err(OnEmpty::get())`. Errors are not stored.
+pub struct ResultQuery<E, OnEmpty>(PhantomData<(E, OnEmpty)>);
+
+impl<V, E, OnEmpty: Get<E>> QueryKindTrait<V> for ResultQuery<E, OnEmpty> {
+	type Query = Result<V, E>;
+
+	fn from_optional_value(value: Option<V>) -> Self::Query {
+		value.ok_or_else(OnEmpty::get)
+	}
+
+	fn into_optional_value(query: Self::Query) -> Option<V> {
+		query.ok()
+	}
+}
+
+/// A [`Get`] for the [`Default`] value of any type.
+pub struct GetDefault;
+
+impl<T: Default> Get<T> for GetDefault {
+	fn get() -> T {
+		T::default()
+	}
+}
+
+/// What should be in the storage for `value`, according to the query kind `Q`. E.g. `None` for the
+/// default of a [`ValueQuery`].
+pub(crate) fn normalize<V, Q: QueryKindTrait<V>>(value: Option<V>) -> Option<V> {
+	Q::into_optional_value(Q::from_optional_value(value))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	struct Seven;
+	impl Get<u32> for Seven {
+		fn get() -> u32 {
+			7
+		}
+	}
+
+	struct Missing;
+	impl Get<&'static str> for Missing {
+		fn get() -> &'static str {
+			"missing"
+		}
+	}
+
+	#[test]
+	fn missing_values() {
+		assert_eq!(
+			<OptionQuery as QueryKindTrait<u32>>::from_optional_value(None),
+			None
+		);
+		assert_eq!(
+			<ValueQuery as QueryKindTrait<u32>>::from_optional_value(None),
+			0
+		);
+		assert_eq!(
+			<ValueQuery<Seven> as QueryKindTrait<u32>>::from_optional_value(None),
+			7
+		);
+		assert_eq!(
+			<ResultQuery<_, Missing> as QueryKindTrait<u32>>::from_optional_value(None),
+			Err("missing")
+		);
+		assert_eq!(
+			<ResultQuery<_, Missing> as QueryKindTrait<u32>>::from_optional_value(Some(1)),
+			Ok(1)
+		);
+	}
+
+	#[test]
+	fn defaults_are_not_stored() {
+		assert_eq!(normalize::<u32, OptionQuery>(Some(0)), Some(0));
+		assert_eq!(normalize::<u32, ValueQuery>(Some(0)), None);
+		assert_eq!(normalize::<u32, ValueQuery>(Some(7)), Some(7));
+		assert_eq!(normalize::<u32, ValueQuery<Seven>>(Some(7)), None);
+		assert_eq!(normalize::<u32, ValueQuery<Seven>>(Some(0)), Some(0));
+		assert_eq!(normalize::<u32, ResultQuery<_, Missing>>(Some(0)), Some(0));
+	}
+}
@@ -0,0 +1,202 @@
+//! A registry of all the storage items of a runtime, to catch overlapping keys early.
+//!
+//! Every storage item lives under a prefix derived from its module and its own name, see
+//! [`shared::storage_prefix`]. Two items with the same module and name, or an item that overrides
+//! its key by hand, can still end up sharing keys and silently overwriting each other's data.
+//!
+//! A runtime lists all of its items in a [`StorageRegistry`], and calls
+//! [`StorageRegistry::check`] at startup, which reports the first two items whose keys may
+//! overlap, i.e. where the prefix of one item starts with the prefix of the other.
+
+use super::{
+	io_storage::Key,
+	shared::{self, StorageDoubleMap, StorageMap, StorageNMap, StorageValue},
+};
+use std::fmt;
+
+/// A single storage item, as known to the [`StorageRegistry`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StorageItem {
+	/// The name of the module that the item belongs to.
+	pub module: &'static str,
+	/// The name of the item.
+	pub name: &'static str,
+	/// The prefix of all storage keys of the item.
+	pub prefix: Key,
+}
+
+impl StorageItem {
+	/// The item called `name` within `module`, under its default prefix.
+	pub fn new(module: &'static str, name: &'static str) -> Self {
+		Self {
+			module,
+			name,
+			prefix: shared::storage_prefix(module, name),
+		}
+	}
+
+	/// The description of the storage value `S`.
+	pub fn value<S: StorageValue>() -> Self {
+		Self {
+			module: S::module_prefix(),
+			name: S::storage_prefix(),
+			prefix: S::raw_storage_key(),
+		}
+	}
+
+	/// The description of the storage map `M`.
+	pub fn map<M: StorageMap>() -> Self {
+		Self {
+			module: M::module_prefix(),
+			name: M::storage_prefix(),
+			prefix: M::prefix(),
+		}
+	}
+
+	/// The description of the storage double map `M`.
+	pub fn double_map<M: StorageDoubleMap>() -> Self {
+		Self {
+			module: M::module_prefix(),
+			name: M::storage_prefix(),
+			prefix: M::prefix(),
+		}
+	}
+
+	/// The description of the storage n-map `M`.
+	pub fn n_map<M: StorageNMap>() -> Self {
+		Self {
+			module: M::module_prefix(),
+			name: M::storage_prefix(),
+			prefix: M::prefix(),
+		}
+	}
+}
+
+impl fmt::Display for StorageItem {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(f, "{}::{}", self.module, self.name)
+	}
+}
+
+/// Two storage items whose keys may overlap, as reported by [`StorageRegistry::check`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PrefixCollision {
+	/// The item with the shorter (or equal) prefix.
+	pub first: StorageItem,
+	/// The item whose prefix starts with the prefix of `first`.
+	pub second: StorageItem,
+}
+
+impl fmt::Display for PrefixCollision {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(
+			f,
+			"the storage keys of {} and {} overlap",
+			self.first, self.second
+		)
+	}
+}
+
+/// All the storage items of a runtime.
+///
+/// ```
+/// # use pba_pre_course_assignment::l_mini_substrate::{
+/// #     currency_module::{BalancesMap, TotalIssuance},
+/// #     registry::{StorageItem, StorageRegistry},
+/// #     runtime::MyRuntime,
+/// # };
+/// let registry = StorageRegistry::default()
+///     .with(StorageItem::value::<TotalIssuance<MyRuntime>>())
+///     .with(StorageItem::map::<BalancesMap<MyRuntime>>());
+/// assert!(registry.check().is_ok());
+///
+/// // registering the same item twice is a collision.
+/// let registry = registry.with(StorageItem::value::<TotalIssuance<MyRuntime>>());
+/// assert!(registry.check().is_err());
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct StorageRegistry {
+	items: Vec<StorageItem>,
+}
+
+impl StorageRegistry {
+	/// Add `item` to the registry.
+	pub fn with(mut self, item: StorageItem) -> Self {
+		self.items.push(item);
+		self
+	}
+
+	/// All the registered items, in order of registration.
+	pub fn items(&self) -> &[StorageItem] {
+		&self.items
+	}
+
+	/// Check that the keys of no two registered items overlap.
+	pub fn check(&self) -> Result<(), PrefixCollision> {
+		let mut sorted = self.items.iter().collect::<Vec<_>>();
+		sorted.sort_by(|a, b| a.prefix.cmp(&b.prefix));
+		// if a prefix starts with another one, it is sorted right after it, or after other items
+		// that start with it as well. Checking neighbours is thus enough.
+		for pair in sorted.windows(2) {
+			if pair[1].prefix.starts_with(&pair[0].prefix) {
+				return Err(PrefixCollision {
+					first: pair[0].clone(),
+					second: pair[1].clone(),
+				});
+			}
+		}
+		Ok(())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn same_name_in_different_modules_is_fine() {
+		let registry = StorageRegistry::default()
+			.with(StorageItem::new("Balances", "TotalIssuance"))
+			.with(StorageItem::new("Assets", "TotalIssuance"));
+		assert_eq!(registry.check(), Ok(()));
+	}
+
+	#[test]
+	fn duplicate_items_collide() {
+		let registry = StorageRegistry::default()
+			.with(StorageItem::new("Balances", "TotalIssuance"))
+			.with(StorageItem::new("Assets", "Accounts"))
+			.with(StorageItem::new("Balances", "TotalIssuance"));
+		let collision = registry.check().unwrap_err();
+		assert_eq!(
+			collision.first,
+			StorageItem::new("Balances", "TotalIssuance")
+		);
+		assert_eq!(
+			collision.to_string(),
+			"the storage keys of Balances::TotalIssuance and Balances::TotalIssuance overlap"
+		);
+	}
+
+	#[test]
+	fn nested_prefixes_collide() {
+		// e.g. an item that picked its key by hand, right under the prefix of a map.
+		let map = StorageItem::new("Balances", "Accounts");
+		let nested = StorageItem {
+			module: "Other",
+			name: "Sneaky",
+			prefix: [map.prefix.as_slice(), b"sneaky"].concat(),
+		};
+		let registry = StorageRegistry::default()
+			.with(nested.clone())
+			.with(StorageItem::new("System", "Number"))
+			.with(map.clone());
+		assert_eq!(
+			registry.check(),
+			Err(PrefixCollision {
+				first: map,
+				second: nested
+			})
+		);
+	}
+}
@@ -0,0 +1,218 @@
+//! Keys made of several parts, each hashed with its own hasher, as used by
+//! [`shared::StorageNMap`](super::shared::StorageNMap).
+//!
+//! A single part is an [`NMapKey`], which names the type of that part along with its hasher. Parts
+//! are grouped in tuples, e.g. `(NMapKey<Blake2_128Concat, u32>, NMapKey<Identity, AccountId>)`,
+//! whose key is the tuple of the keys of each part, i.e. `(u32, AccountId)`. The final key is the
+//! concatenation of the hashed encoding of each part, in order.
+//!
+//! All entries that share the same leading parts are thus stored next to each other. Which leading
+//! parts a tuple has, and what remains after them, is described by [`HasKeyPrefix`].
+//!
+//! The tuple implementations are generated by macros, in the same fashion as `impl_for_tuples!` in
+//! `n_macros_2`, for up to 12 parts.
+
+use super::hashing::{ReversibleStorageHasher, StorageHasher};
+use parity_scale_codec::{Decode, Encode};
+use std::marker::PhantomData;
+
+/// A single part of a key, of type `K`, hashed with `H`.
+pub struct NMapKey<H, K>(PhantomData<(H, K)>);
+
+/// Something that turns a key into (part of) a storage key.
+pub trait KeyGenerator {
+	/// The type of the key, e.g. a tuple with a value for each part.
+	type Key;
+
+	/// Append the hashed encoding of each part of `key` to `raw_key`.
+	fn hash_key(key: Self::Key, raw_key: &mut Vec<u8>);
+
+	/// The hashed encoding of each part of `key`, concatenated.
+	fn final_key(key: Self::Key) -> Vec<u8> {
+		let mut raw_key = Vec::new();
+		Self::hash_key(key, &mut raw_key);
+		raw_key
+	}
+}
+
+/// A [`KeyGenerator`] whose keys can be recovered out of their hashed encoding. This is the case
+/// if every part uses a [`ReversibleStorageHasher`].
+pub trait ReversibleKeyGenerator: KeyGenerator {
+	/// Decode a key from the front of `input`, and advance `input` past it.
+	///
+	/// `input` is left untouched if no key can be decoded.
+	fn decode_key(input: &mut &[u8]) -> Option<Self::Key>;
+
+	/// Decode a key out of its [`KeyGenerator::final_key`], and nothing more.
+	fn decode_final_key(mut input: &[u8]) -> Option<Self::Key> {
+		let key = Self::decode_key(&mut input)?;
+		input.is_empty().then_some(key)
+	}
+}
+
+/// A [`KeyGenerator`] whose leading parts have the keys `P`, e.g. `(u32,)` for any tuple of parts
+/// that starts with a `u32`.
+pub trait HasKeyPrefix<P>: KeyGenerator {
+	/// The generator of the remaining parts, after the prefix.
+	type Suffix: KeyGenerator;
+
+	/// The hashed encoding of the leading parts, concatenated.
+	fn prefix_key(prefix: P) -> Vec<u8>;
+}
+
+/// The key of whatever follows the prefix `P` in the key generator `K`.
+pub type SuffixKey<K, P> = <<K as HasKeyPrefix<P>>::Suffix as KeyGenerator>::Key;
+
+impl<H: StorageHasher, K: Encode> KeyGenerator for NMapKey<H, K> {
+	type Key = K;
+
+	fn hash_key(key: K, raw_key: &mut Vec<u8>) {
+		raw_key.extend_from_slice(H::hash(&key.encode()).as_ref());
+	}
+}
+
+impl<H: ReversibleStorageHasher, K: Encode + Decode> ReversibleKeyGenerator for NMapKey<H, K> {
+	fn decode_key(input: &mut &[u8]) -> Option<K> {
+		let mut encoded = H::reverse(input)?;
+		let key = K::decode(&mut encoded).ok()?;
+		let (hashed, rest) = input.split_at(input.len() - encoded.len());
+		// make sure that the hash, and nothing else, precedes the key.
+		if H::hash(&key.encode()).as_ref() != hashed {
+			return None;
+		}
+		*input = rest;
+		Some(key)
+	}
+}
+
+/// Implement [`HasKeyPrefix`] for a tuple made of the parts in both lists, for every split of it
+/// into leading parts and at least one remaining part. A single remaining part is its own suffix,
+/// rather than a tuple of one.
+macro_rules! impl_key_prefixes {
+	([$($prefix:ident),+] []) => {};
+
+	([$($prefix:ident),+] [$last:ident]) => {
+		impl<$($prefix: KeyGenerator,)+ $last: KeyGenerator> HasKeyPrefix<($($prefix::Key,)+)>
+			for ($($prefix,)+ $last,)
+		{
+			type Suffix = $last;
+
+			fn prefix_key(prefix: ($($prefix::Key,)+)) -> Vec<u8> {
+				<($($prefix,)+)>::final_key(prefix)
+			}
+		}
+	};
+
+	([$($prefix:ident),+] [$next:ident, $($rest:ident),+]) => {
+		impl<$($prefix: KeyGenerator,)+ $next: KeyGenerator, $($rest: KeyGenerator),+>
+			HasKeyPrefix<($($prefix::Key,)+)> for ($($prefix,)+ $next, $($rest,)+)
+		{
+			type Suffix = ($next, $($rest,)+);
+
+			fn prefix_key(prefix: ($($prefix::Key,)+)) -> Vec<u8> {
+				<($($prefix,)+)>::final_key(prefix)
+			}
+		}
+
+		impl_key_prefixes!([$($prefix,)+ $next] [$($rest),+]);
+	};
+}
+
+/// Implement [`KeyGenerator`], [`ReversibleKeyGenerator`] and [`HasKeyPrefix`] for all tuples of
+/// parts, up to the given number of them.
+macro_rules! impl_key_generator_for_tuples {
+	() => {};
+
+	($head:ident $(, $tail:ident)*) => {
+		impl<$head: KeyGenerator, $($tail: KeyGenerator),*> KeyGenerator for ($head, $($tail,)*) {
+			type Key = ($head::Key, $($tail::Key,)*);
+
+			#[allow(non_snake_case)]
+			fn hash_key(key: Self::Key, raw_key: &mut Vec<u8>) {
+				let ($head, $($tail,)*) = key;
+				$head::hash_key($head, raw_key);
+				$($tail::hash_key($tail, raw_key);)*
+			}
+		}
+
+		impl<$head, $($tail),*> ReversibleKeyGenerator for ($head, $($tail,)*)
+		where
+			$head: ReversibleKeyGenerator,
+			$($tail: ReversibleKeyGenerator,)*
+		{
+			fn decode_key(input: &mut &[u8]) -> Option<Self::Key> {
+				let mut rest = *input;
+				let key = ($head::decode_key(&mut rest)?, $($tail::decode_key(&mut rest)?,)*);
+				*input = rest;
+				Some(key)
+			}
+		}
+
+		impl_key_prefixes!([$head] [$($tail),*]);
+
+		impl_key_generator_for_tuples!($($tail),*);
+	};
+}
+impl_key_generator_for_tuples!(A, B, C, D, E, F, G, H, I, J, K, L);
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::l_mini_substrate::hashing::{Blake2_128Concat, Identity, Twox64Concat};
+
+	type Parts = (
+		NMapKey<Blake2_128Concat, u32>,
+		NMapKey<Twox64Concat, u16>,
+		NMapKey<Identity, u8>,
+	);
+
+	#[test]
+	fn parts_are_hashed_in_order() {
+		assert_eq!(
+			Parts::final_key((1, 2, 3)),
+			[
+				Blake2_128Concat::hash(&1u32.encode()),
+				Twox64Concat::hash(&2u16.encode()),
+				vec![3]
+			]
+			.concat()
+		);
+		assert_eq!(
+			<Parts as HasKeyPrefix<(u32, u16)>>::prefix_key((1, 2)),
+			[
+				Blake2_128Concat::hash(&1u32.encode()),
+				Twox64Concat::hash(&2u16.encode())
+			]
+			.concat()
+		);
+		let prefix = <Parts as HasKeyPrefix<(u32,)>>::prefix_key((1,));
+		assert!(Parts::final_key((1, 2, 3)).starts_with(&prefix));
+	}
+
+	#[test]
+	fn keys_can_be_decoded() {
+		let raw_key = Parts::final_key((1, 2, 3));
+		assert_eq!(Parts::decode_final_key(&raw_key), Some((1, 2, 3)));
+
+		// suffixes decode what is left after their prefix.
+		let prefix_len = <Parts as HasKeyPrefix<(u32,)>>::prefix_key((1,)).len();
+		let suffix = &raw_key[prefix_len..];
+		assert_eq!(
+			<<Parts as HasKeyPrefix<(u32,)>>::Suffix>::decode_final_key(suffix),
+			Some((2, 3))
+		);
+
+		// trailing or tampered bytes are not a key.
+		assert_eq!(
+			Parts::decode_final_key(&[raw_key.as_slice(), &[0]].concat()),
+			None
+		);
+		let mut tampered = raw_key.clone();
+		tampered[0] ^= 1;
+		assert_eq!(Parts::decode_final_key(&tampered), None);
+
+		let mut input = &tampered[..];
+		assert_eq!(Parts::decode_key(&mut input), None);
+		assert_eq!(input, &tampered[..]);
+	}
+}
@@ -26,10 +26,10 @@ impl OnInitialize for Module4 {
 	fn on_initialize() {}
 }
 
-/// Now, we want to write a macro that reduces the boilerplate of implementing `OnInitialize` on
-/// tuples of elements that each individually implement `OnInitialize`.
-///
-/// For example, for a tuple of 4 elements, we could have:
+// Now, we want to write a macro that reduces the boilerplate of implementing `OnInitialize` on
+// tuples of elements that each individually implement `OnInitialize`.
+//
+// For example, for a tuple of 4 elements, we could have:
 
 // impl<A, B, C, D> OnInitialize for (A, B, C, D)
 // where